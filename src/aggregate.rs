@@ -5,7 +5,7 @@ use std::{
     str::FromStr,
 };
 
-use crate::Prefix;
+use crate::{Map, Prefix};
 
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
 pub struct AggPrefix {
@@ -53,6 +53,21 @@ impl AggPrefix {
 }
 
 impl AggPrefix {
+    /// Whether `prefix` is permitted by this entry.
+    pub fn matches(&self, (ip, masklen): &Prefix) -> bool {
+        let covered = match (self.prefix, ip) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                self.mask == 0 || (u32::from(a) ^ u32::from(*b)) >> (32 - u32::from(self.mask)) == 0
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                self.mask == 0
+                    || (u128::from(a) ^ u128::from(*b)) >> (128 - u32::from(self.mask)) == 0
+            }
+            _ => false,
+        };
+        covered && (self.min..=self.max).contains(masklen)
+    }
+
     pub fn from_prefix((ip, masklen): &Prefix) -> Self {
         AggPrefix {
             prefix: *ip,
@@ -62,23 +77,141 @@ impl AggPrefix {
             valid: true,
         }
     }
+
+    fn range(prefix: IpAddr, mask: u8, min: u8, max: u8) -> Self {
+        AggPrefix {
+            prefix: truncate(prefix, mask),
+            mask,
+            min,
+            max,
+            valid: true,
+        }
+    }
+
+    /// Whether the network `ip/len` lies within this entry's network.
+    fn covers_network(&self, ip: IpAddr, len: u8) -> bool {
+        self.prefix.is_ipv4() == ip.is_ipv4()
+            && self.mask <= len
+            && truncate(ip, self.mask) == truncate(self.prefix, self.mask)
+    }
+
+    /// The entry permitting exactly the prefixes permitted by both, if any.
+    pub fn intersect(&self, other: &Self) -> Option<AggPrefix> {
+        let (outer, inner) = if self.mask <= other.mask {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let (min, max) = (max(self.min, other.min), min(self.max, other.max));
+        if outer.covers_network(inner.prefix, inner.mask) && min <= max {
+            Some(AggPrefix::range(inner.prefix, inner.mask, min, max))
+        } else {
+            None
+        }
+    }
+
+    /// Entries permitting the prefixes permitted by `self` but not by `other`.
+    pub fn subtract(&self, other: &Self) -> Vec<AggPrefix> {
+        if self.intersect(other).is_none() {
+            return vec![self.clone()];
+        }
+        let mut ret = Vec::new();
+        if other.mask <= self.mask {
+            // same or wider network: only the lengths outside other's range remain
+            if self.min < other.min {
+                ret.push(AggPrefix::range(
+                    self.prefix,
+                    self.mask,
+                    self.min,
+                    other.min - 1,
+                ));
+            }
+            if other.max < self.max {
+                ret.push(AggPrefix::range(
+                    self.prefix,
+                    self.mask,
+                    other.max + 1,
+                    self.max,
+                ));
+            }
+        } else {
+            // prefixes shorter than other's network are unaffected
+            if self.min < other.mask {
+                ret.push(AggPrefix::range(
+                    self.prefix,
+                    self.mask,
+                    self.min,
+                    min(self.max, other.mask - 1),
+                ));
+            }
+            // the longer ones are split along the path down to other's network
+            let min = max(self.min, other.mask);
+            for len in self.mask + 1..=other.mask {
+                let sibling = flip_bit(truncate(other.prefix, len), len);
+                ret.push(AggPrefix::range(sibling, len, min, self.max));
+            }
+            let below = AggPrefix::range(other.prefix, other.mask, min, self.max);
+            ret.extend(below.subtract(other));
+        }
+        ret
+    }
+}
+
+/// Entries permitting the prefixes permitted by one of `entries` and one of `other`.
+pub fn intersect_entries(entries: &[AggPrefix], other: &[AggPrefix]) -> Vec<AggPrefix> {
+    entries
+        .iter()
+        .flat_map(|a| other.iter().filter_map(move |b| a.intersect(b)))
+        .collect()
+}
+
+/// Entries permitting the prefixes permitted by one of `entries` but none of `other`.
+pub fn subtract_entries(mut entries: Vec<AggPrefix>, other: &[AggPrefix]) -> Vec<AggPrefix> {
+    for b in other {
+        entries = entries.iter().flat_map(|a| a.subtract(b)).collect();
+    }
+    entries
 }
 
 impl FromStr for AggPrefix {
-    type Err = Box<dyn Error>;
+    type Err = Box<dyn Error + Send + Sync>;
 
+    /// Parses `prefix/len`, optionally followed by `ge n` and/or `le n`.
     fn from_str(s: &str) -> Result<AggPrefix, Self::Err> {
-        let mut elems = s.split('/');
+        let mut words = s.split_whitespace();
+        let mut elems = words.next().unwrap_or("").split('/');
         if let (Some(ip), Some(mask), None) = (elems.next(), elems.next(), elems.next()) {
             let prefix = ip.parse()?;
             let mask = mask.parse()?;
-            Ok(AggPrefix {
+            let mut entry = AggPrefix {
                 prefix,
                 mask,
                 min: mask,
                 max: mask,
                 valid: true,
-            })
+            };
+            let mut le_given = false;
+            while let Some(op) = words.next() {
+                let len: u8 = words.next().ok_or("missing length")?.parse()?;
+                match op {
+                    "ge" => {
+                        entry.min = len;
+                        if !le_given {
+                            entry.max = if prefix.is_ipv4() { 32 } else { 128 };
+                        }
+                    }
+                    "le" => {
+                        entry.max = len;
+                        le_given = true;
+                    }
+                    _ => return Err(format!("invalid operator {}", op).into()),
+                }
+            }
+            let family_len = if prefix.is_ipv4() { 32 } else { 128 };
+            if !(mask <= entry.min && entry.min <= entry.max && entry.max <= family_len) {
+                return Err(format!("invalid prefix range {}", s).into());
+            }
+            Ok(entry)
         } else {
             Err("invalid prefix".into())
         }
@@ -103,8 +236,8 @@ fn consolidate(level: &mut Vec<AggPrefix>, level_below: &mut Vec<AggPrefix>) {
                         prefix.valid = false;
                         level_below.push(merged);
                         did_change = true;
-                    } else if (first.prefix, first.mask, first.min + 1)
-                        == (prefix.prefix, prefix.mask, prefix.min)
+                    } else if (first.prefix, first.mask) == (prefix.prefix, prefix.mask)
+                        && prefix.min <= first.max + 1
                     {
                         // {192.0.2.0/23 ge 24 le 24, 192.0.2.0/23 ge 25 le 25} -> {192.0.2.0/23 ge 24 le 25}
                         first.min = min(first.min, prefix.min);
@@ -122,10 +255,22 @@ fn consolidate(level: &mut Vec<AggPrefix>, level_below: &mut Vec<AggPrefix>) {
 }
 
 pub fn aggregate(prefixes: &[&Prefix]) -> Vec<AggPrefix> {
-    let prefixes: Vec<AggPrefix> = prefixes.iter().map(|p| AggPrefix::from_prefix(p)).collect();
+    aggregate_entries(prefixes.iter().map(|p| AggPrefix::from_prefix(p)).collect())
+}
+
+/// Aggregates entries that may already permit a range of lengths (`min != max`),
+/// such as ROAs with a maxLength.
+pub fn aggregate_entries(entries: Vec<AggPrefix>) -> Vec<AggPrefix> {
+    // plain prefixes can't cover each other, so skip the extra passes for them
+    let has_ranges = entries.iter().any(|entry| entry.min != entry.max);
+    let entries = if has_ranges {
+        remove_covered(entries)
+    } else {
+        entries
+    };
     let mut levels = Vec::<Vec<AggPrefix>>::new();
     levels.resize_with(129, Vec::new);
-    prefixes
+    entries
         .into_iter()
         .for_each(|p| levels[p.mask as usize].push(p));
     let mut view = levels.as_mut_slice();
@@ -135,9 +280,187 @@ pub fn aggregate(prefixes: &[&Prefix]) -> Vec<AggPrefix> {
         }
         view = rest;
     }
-    levels
+    let entries = levels
         .into_iter()
         .flat_map(IntoIterator::into_iter)
         .filter(|entry| entry.valid)
-        .collect()
+        .collect();
+    if has_ranges {
+        remove_covered(entries)
+    } else {
+        entries
+    }
+}
+
+/// Drops entries whose prefixes are all permitted by another entry.
+fn remove_covered(mut entries: Vec<AggPrefix>) -> Vec<AggPrefix> {
+    entries.sort_unstable();
+    entries.dedup();
+    let mut by_network: Map<Prefix, Vec<(u8, u8)>> = Map::new();
+    for entry in entries.iter() {
+        by_network
+            .entry((entry.prefix, entry.mask))
+            .or_default()
+            .push((entry.min, entry.max));
+    }
+    entries.retain(|entry| {
+        !(0..=entry.mask).any(|len| {
+            let ranges = match by_network.get(&(truncate(entry.prefix, len), len)) {
+                Some(ranges) => ranges,
+                None => return false,
+            };
+            ranges.iter().any(|&(min, max)| {
+                min <= entry.min
+                    && entry.max <= max
+                    && (len, min, max) != (entry.mask, entry.min, entry.max)
+            })
+        })
+    });
+    entries
+}
+
+/// Clears the host bits of `ip` beyond `len`.
+pub fn truncate(ip: IpAddr, len: u8) -> IpAddr {
+    match ip {
+        // lengths beyond the address keep every bit
+        IpAddr::V4(ip) => {
+            let mask = match 32u32.checked_sub(u32::from(len)) {
+                Some(host_bits) => u32::MAX.checked_shl(host_bits).unwrap_or(0),
+                None => u32::MAX,
+            };
+            IpAddr::V4((u32::from(ip) & mask).into())
+        }
+        IpAddr::V6(ip) => {
+            let mask = match 128u32.checked_sub(u32::from(len)) {
+                Some(host_bits) => u128::MAX.checked_shl(host_bits).unwrap_or(0),
+                None => u128::MAX,
+            };
+            IpAddr::V6((u128::from(ip) & mask).into())
+        }
+    }
+}
+
+/// Flips the last bit of the `len` bit network of `ip`.
+fn flip_bit(ip: IpAddr, len: u8) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) ^ (1 << (32 - u32::from(len)))).into()),
+        IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) ^ (1 << (128 - u32::from(len)))).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(input: &[&str]) -> Vec<AggPrefix> {
+        input.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn aggregates_prefixes() {
+        let prefixes: Vec<Prefix> = ["192.0.2.0/24", "192.0.3.0/24", "192.0.2.0/25"]
+            .iter()
+            .map(|s| crate::irr::parse_prefix(s).unwrap())
+            .collect();
+        let prefixes: Vec<&Prefix> = prefixes.iter().collect();
+        assert_eq!(
+            aggregate(&prefixes),
+            entries(&["192.0.2.0/23 ge 24 le 24", "192.0.2.0/25"])
+        );
+    }
+
+    #[test]
+    fn aggregates_ranges() {
+        let mut aggregated = aggregate_entries(entries(&[
+            "10.0.0.0/8 le 24",
+            "10.1.0.0/16",
+            "10.1.0.0/24 le 28",
+            "198.51.100.0/24 le 25",
+            "198.51.101.0/24 le 25",
+            "198.51.100.0/24 ge 25 le 26",
+        ]));
+        aggregated.sort_unstable();
+        assert_eq!(
+            aggregated,
+            entries(&[
+                "10.0.0.0/8 le 24",
+                "10.1.0.0/24 le 28",
+                "198.51.100.0/24 le 26",
+                "198.51.101.0/24 le 25",
+            ])
+        );
+    }
+
+    #[test]
+    fn intersects_and_subtracts() {
+        let entry = |s: &str| s.parse::<AggPrefix>().unwrap();
+        assert_eq!(
+            entry("10.0.0.0/8 le 24").intersect(&entry("10.1.0.0/16 le 32")),
+            Some(entry("10.1.0.0/16 le 24"))
+        );
+        assert_eq!(
+            entry("10.0.0.0/8").intersect(&entry("10.1.0.0/16 le 32")),
+            None
+        );
+        assert_eq!(
+            entry("10.0.0.0/8 le 32").subtract(&entry("0.0.0.0/0 ge 25 le 32")),
+            entries(&["10.0.0.0/8 le 24"])
+        );
+        let mut rest = entry("192.0.2.0/24 le 26").subtract(&entry("192.0.2.64/26"));
+        rest.sort_unstable();
+        assert_eq!(
+            rest,
+            entries(&[
+                "192.0.2.0/24 le 25",
+                "192.0.2.0/26 ge 26 le 26",
+                "192.0.2.128/25 ge 26 le 26",
+            ])
+        );
+        // the remainder permits exactly what the subtraction should leave
+        let all: Vec<Prefix> = (24..=26)
+            .flat_map(|len| {
+                (0..4u8).map(move |i| (format!("192.0.2.{}", i * 64).parse().unwrap(), len))
+            })
+            .map(|(ip, len)| (truncate(ip, len), len))
+            .collect();
+        for prefix in all {
+            assert_eq!(
+                rest.iter().any(|e| e.matches(&prefix)),
+                prefix != ("192.0.2.64".parse().unwrap(), 26),
+                "{:?}",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn exclusions_survive_aggregation() {
+        let entries: Vec<AggPrefix> = (0..4)
+            .map(|i| format!("10.0.{}.0/24", i).parse().unwrap())
+            .chain(std::iter::once("10.1.0.0/16 le 24".parse().unwrap()))
+            .collect();
+        let exclude: Vec<AggPrefix> = vec![
+            "10.0.1.0/24".parse().unwrap(),
+            "10.1.128.0/17 le 32".parse().unwrap(),
+        ];
+        let entries = aggregate_entries(subtract_entries(entries, &exclude));
+        let permits = |s: &str| {
+            let prefix = crate::irr::parse_prefix(s).unwrap();
+            entries.iter().any(|entry| entry.matches(&prefix))
+        };
+        assert!(permits("10.0.0.0/24") && permits("10.0.2.0/24") && permits("10.0.3.0/24"));
+        assert!(!permits("10.0.1.0/24") && !permits("10.0.0.0/22"));
+        assert!(permits("10.1.0.0/16") && permits("10.1.64.0/18"));
+        assert!(!permits("10.1.128.0/17") && !permits("10.1.200.0/24"));
+    }
+
+    #[test]
+    fn truncates_addresses() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(truncate(ip("192.0.2.255"), 24), ip("192.0.2.0"));
+        assert_eq!(truncate(ip("192.0.2.255"), 0), ip("0.0.0.0"));
+        assert_eq!(truncate(ip("192.0.2.255"), 33), ip("192.0.2.255"));
+        assert_eq!(truncate(ip("2001:db8::1"), 32), ip("2001:db8::"));
+        assert_eq!(truncate(ip("2001:db8::1"), 200), ip("2001:db8::1"));
+    }
 }
@@ -0,0 +1,143 @@
+//! Which AS numbers may appear in a filter.
+
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use crate::{irr::parse_autnum, AppResult};
+
+/// AS numbers that never originate routes on the Internet: AS0 (RFC 7607),
+/// AS_TRANS (RFC 6793), documentation (RFC 5398), private (RFC 6996) and
+/// reserved ones.
+pub const RESERVED: &[RangeInclusive<u32>] = &[
+    0..=0,
+    23_456..=23_456,
+    64_496..=65_535,
+    4_200_000_000..=4_294_967_294,
+];
+
+/// A single AS number (`AS174`) or a range (`AS64496-AS64511`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsnRange(pub RangeInclusive<u32>);
+
+impl FromStr for AsnRange {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bounds = s.splitn(2, '-').map(str::trim);
+        let first = parse_autnum(bounds.next().unwrap_or(""))?;
+        let last = match bounds.next() {
+            Some(last) => parse_autnum(last)?,
+            None => first,
+        };
+        if first > last {
+            return Err(format!("invalid AS range {}", s).into());
+        }
+        Ok(AsnRange(first..=last))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Removal {
+    /// in the built-in list of reserved AS numbers
+    Reserved,
+    /// in the configured exclude list
+    Excluded,
+}
+
+impl fmt::Display for Removal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Removal::Reserved => "reserved",
+            Removal::Excluded => "excluded",
+        })
+    }
+}
+
+/// Sanitization policy for as-set members.
+///
+/// Included AS numbers are always kept; otherwise excluded and (unless turned
+/// off) reserved ones are removed.
+#[derive(Debug, Clone)]
+pub struct AsnPolicy {
+    pub drop_reserved: bool,
+    pub include: Vec<AsnRange>,
+    pub exclude: Vec<AsnRange>,
+}
+
+impl Default for AsnPolicy {
+    fn default() -> Self {
+        AsnPolicy {
+            drop_reserved: true,
+            include: vec![],
+            exclude: vec![],
+        }
+    }
+}
+
+impl AsnPolicy {
+    /// Builds a policy from `AS174` / `AS64496-AS64511` style strings.
+    pub fn new<S: AsRef<str>>(
+        drop_reserved: bool,
+        include: &[S],
+        exclude: &[S],
+    ) -> AppResult<Self> {
+        let parse = |list: &[S]| {
+            list.iter()
+                .map(|s| s.as_ref().parse::<AsnRange>())
+                .collect::<AppResult<Vec<_>>>()
+        };
+        Ok(AsnPolicy {
+            drop_reserved,
+            include: parse(include)?,
+            exclude: parse(exclude)?,
+        })
+    }
+
+    /// Why `asn` has to be removed, if it does.
+    pub fn check(&self, asn: u32) -> Option<Removal> {
+        let within = |ranges: &[AsnRange]| ranges.iter().any(|r| r.0.contains(&asn));
+        if within(&self.include) {
+            None
+        } else if within(&self.exclude) {
+            Some(Removal::Excluded)
+        } else if self.drop_reserved && RESERVED.iter().any(|r| r.contains(&asn)) {
+            Some(Removal::Reserved)
+        } else {
+            None
+        }
+    }
+
+    /// Splits `members` into the ones kept and the ones removed, with the reason.
+    pub fn apply(&self, members: &[u32]) -> (Vec<u32>, Vec<(u32, Removal)>) {
+        let mut kept = Vec::with_capacity(members.len());
+        let mut removed = Vec::new();
+        for asn in members.iter().copied() {
+            match self.check(asn) {
+                Some(reason) => removed.push((asn, reason)),
+                None => kept.push(asn),
+            }
+        }
+        (kept, removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_policy() {
+        let policy = AsnPolicy::new(true, &["AS64496-AS64511"], &["AS174"]).unwrap();
+        let (kept, removed) = policy.apply(&[0, 174, 3333, 64500, 64512]);
+        assert_eq!(kept, vec![3333, 64500]);
+        assert_eq!(
+            removed,
+            vec![
+                (0, Removal::Reserved),
+                (174, Removal::Excluded),
+                (64512, Removal::Reserved)
+            ]
+        );
+        assert_eq!(AsnPolicy::default().check(23_456), Some(Removal::Reserved));
+        assert!("AS2-AS1".parse::<AsnRange>().is_err());
+    }
+}
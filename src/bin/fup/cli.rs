@@ -0,0 +1,409 @@
+use std::net::Ipv6Addr;
+
+use fup::format::Style;
+
+use crate::snapshot::Format;
+
+/// What fup was asked to do.
+#[derive(Debug)]
+pub enum Command {
+    /// generate the filters of every router in a configuration file
+    Run(RunArgs),
+    /// print what would change since the last run, without writing anything
+    Report(ReportArgs),
+    /// explain why a prefix is in a filter: configuration, filter, prefix
+    Explain(String, String, String),
+    /// print a single filter to stdout, without a configuration file
+    Query(Query),
+    Help,
+}
+
+/// A configuration file to generate filters from, and how.
+#[derive(Debug)]
+pub struct RunArgs {
+    pub config: String,
+    /// write filters even if they shrank beyond their thresholds
+    pub allow_shrink: bool,
+    /// IRR snapshot to use instead of the servers
+    pub from_snapshot: Option<String>,
+    /// write files without applying them to routers
+    pub no_deploy: bool,
+    /// write files without running hooks
+    pub no_hooks: bool,
+}
+
+/// A configuration file to report changes for, and the report format.
+#[derive(Debug)]
+pub struct ReportArgs {
+    pub config: String,
+    pub format: Format,
+}
+
+/// A filter to print, as given by `fup query` flags.
+#[derive(Debug)]
+pub struct Query {
+    /// irrd servers, tried in order
+    pub servers: Vec<String>,
+    pub sources: Vec<String>,
+    pub style: Style,
+    /// output name (default: the object name, rewritten as needed)
+    pub name: Option<String>,
+    pub aggregate: bool,
+    /// IRR objects, policies or filter expressions, combined with OR
+    pub objects: Vec<String>,
+}
+
+impl Query {
+    /// The object or filter expression the filter is built from.
+    pub fn expression(&self) -> String {
+        self.objects.join(" OR ")
+    }
+}
+
+const DEFAULT_SERVER: &str = "whois.radb.net:43";
+const DEFAULT_SOURCES: &str = "RADB";
+
+pub fn usage(progname: &str) -> String {
+    format!(
+        "Usage: {0} [run] [options] <config.toml>
+       {0} regenerate --from-snapshot <snapshot.json> [options] <config.toml>
+       {0} report [-f text|markdown|json] <config.toml>
+       {0} explain <config.toml> <filter> <prefix>
+       {0} query [options] <object>...
+       {0} -h|--help
+
+Run options:
+      --allow-shrink       write filters that shrank beyond their thresholds
+      --from-snapshot FILE use the IRR data saved by an earlier run
+      --no-deploy          write files without applying them to routers
+
+Report options:
+  -f, --format FORMAT      text (default), markdown or json
+
+Query options:
+  -h, --host HOST[,HOST]   irrd servers, port 43 unless given, IPv6 as
+                           [addr]:port (default {1})
+  -S, --sources LIST       comma separated sources (default {2})
+  -f, --format STYLE       prefix-list (default) or prefix-set
+  -l, --name NAME          name of the prefix-list or prefix-set
+  -n, --no-aggregate       do not aggregate prefixes",
+        progname, DEFAULT_SERVER, DEFAULT_SOURCES
+    )
+}
+
+impl Command {
+    /// Parses the arguments following the program name.
+    pub fn parse<S: AsRef<str>>(args: &[S]) -> Result<Command, String> {
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        match args.as_slice() {
+            ["-h"] | ["--help"] | ["help"] => Ok(Command::Help),
+            ["run", rest @ ..] => parse_run(rest).map(Command::Run),
+            ["regenerate", rest @ ..] => match parse_run(rest)? {
+                RunArgs {
+                    from_snapshot: None,
+                    ..
+                } => Err("regenerate needs --from-snapshot".to_string()),
+                // rebuilding earlier filters must not reach the routers
+                args => Ok(Command::Run(RunArgs {
+                    no_deploy: true,
+                    no_hooks: true,
+                    ..args
+                })),
+            },
+            ["report", rest @ ..] => parse_report(rest).map(Command::Report),
+            ["explain", config, filter, prefix] => Ok(Command::Explain(
+                config.to_string(),
+                filter.to_string(),
+                prefix.to_string(),
+            )),
+            ["query", rest @ ..] => parse_query(rest).map(Command::Query),
+            ["explain", ..] => Err("wrong number of arguments to explain".to_string()),
+            _ => parse_run(&args).map(Command::Run),
+        }
+    }
+}
+
+fn parse_run(args: &[&str]) -> Result<RunArgs, String> {
+    let mut config = None;
+    let mut allow_shrink = false;
+    let mut from_snapshot = None;
+    let mut no_deploy = false;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--allow-shrink" => allow_shrink = true,
+            "--no-deploy" => no_deploy = true,
+            "--from-snapshot" => {
+                let path = args.next().ok_or("--from-snapshot needs a file")?;
+                from_snapshot = Some(path.to_string());
+            }
+            _ if arg.starts_with("--from-snapshot=") => {
+                from_snapshot = Some(arg["--from-snapshot=".len()..].to_string())
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if config.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => config = Some(arg.to_string()),
+        }
+    }
+    let config = config.ok_or("missing configuration file")?;
+    Ok(RunArgs {
+        config,
+        allow_shrink,
+        from_snapshot,
+        no_deploy,
+        no_hooks: false,
+    })
+}
+
+fn parse_report(args: &[&str]) -> Result<ReportArgs, String> {
+    let mut config = None;
+    let mut format = Format::Text;
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "-f" | "--format" => {
+                format = args.next().ok_or("--format needs a value")?.parse()?;
+            }
+            _ if arg.starts_with("--format=") => format = arg["--format=".len()..].parse()?,
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if config.is_some() => return Err(format!("unexpected argument {}", arg)),
+            _ => config = Some(arg.to_string()),
+        }
+    }
+    let config = config.ok_or("missing configuration file")?;
+    Ok(ReportArgs { config, format })
+}
+
+fn parse_query(args: &[&str]) -> Result<Query, String> {
+    let mut query = Query {
+        servers: vec![DEFAULT_SERVER.to_string()],
+        sources: split_list(DEFAULT_SOURCES),
+        style: Style::PrefixList,
+        name: None,
+        aggregate: true,
+        objects: Vec::new(),
+    };
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        // flags take their value as the next argument or after '='
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value)),
+            _ => (arg, None),
+        };
+        let mut value = || {
+            inline
+                .or_else(|| args.next().copied())
+                .ok_or_else(|| format!("{} needs a value", flag))
+        };
+        match flag {
+            "-h" | "--host" => {
+                query.servers = split_list(value()?)
+                    .iter()
+                    .map(String::as_str)
+                    .map(with_port)
+                    .collect()
+            }
+            "-S" | "--sources" => query.sources = split_list(value()?),
+            "-f" | "--format" => query.style = value()?.parse().map_err(|e| format!("{}", e))?,
+            "-l" | "--name" => query.name = Some(value()?.to_string()),
+            "-n" | "--no-aggregate" => query.aggregate = false,
+            "--" => {
+                query.objects.extend(args.map(|arg| arg.to_string()));
+                break;
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option {}", flag))
+            }
+            _ => query.objects.push(arg.to_string()),
+        }
+    }
+    if query.servers.is_empty() || query.sources.is_empty() {
+        return Err("servers and sources must not be empty".to_string());
+    }
+    if query.objects.is_empty() {
+        return Err("query needs an object, e.g. AS-FOO".to_string());
+    }
+    Ok(query)
+}
+
+/// Adds port 43 to `server` unless it has a port: `host:port`, or
+/// `[addr]:port` for IPv6 addresses.
+fn with_port(server: &str) -> String {
+    if server.parse::<Ipv6Addr>().is_ok() {
+        return format!("[{}]:43", server);
+    }
+    match server.strip_prefix('[') {
+        Some(rest) if rest.ends_with(']') => format!("{}:43", server),
+        Some(_) => server.to_string(),
+        None if server.contains(':') => server.to_string(),
+        None => format!("{}:43", server),
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> RunArgs {
+        match Command::parse(args) {
+            Ok(Command::Run(args)) => args,
+            other => panic!("not a run: {:?}", other),
+        }
+    }
+
+    fn query(args: &[&str]) -> Result<Query, String> {
+        match Command::parse(args)? {
+            Command::Query(query) => Ok(query),
+            other => panic!("not a query: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_runs() {
+        let args = run(&["config.toml"]);
+        assert_eq!(args.config, "config.toml");
+        assert!(!args.allow_shrink && !args.no_deploy && !args.no_hooks);
+        assert_eq!(args.from_snapshot, None);
+
+        let args = run(&[
+            "run",
+            "--allow-shrink",
+            "--no-deploy",
+            "--from-snapshot=irr.json",
+            "c.toml",
+        ]);
+        assert_eq!(args.config, "c.toml");
+        assert!(args.allow_shrink && args.no_deploy);
+        assert_eq!(args.from_snapshot.as_deref(), Some("irr.json"));
+
+        let args = run(&["regenerate", "--from-snapshot", "irr.json", "c.toml"]);
+        assert_eq!(args.from_snapshot.as_deref(), Some("irr.json"));
+        assert!(args.no_deploy && args.no_hooks);
+
+        let parse = |args: &[&str]| Command::parse(args).unwrap_err();
+        assert_eq!(
+            parse(&["regenerate", "c.toml"]),
+            "regenerate needs --from-snapshot"
+        );
+        assert_eq!(
+            parse(&["run", "--from-snapshot"]),
+            "--from-snapshot needs a file"
+        );
+        assert_eq!(
+            parse(&["run", "--bogus", "c.toml"]),
+            "unknown option --bogus"
+        );
+        assert_eq!(parse(&["a.toml", "b.toml"]), "unexpected argument b.toml");
+        assert_eq!(parse(&["run"]), "missing configuration file");
+    }
+
+    #[test]
+    fn parses_other_commands() {
+        assert!(matches!(Command::parse(&["--help"]), Ok(Command::Help)));
+        assert!(matches!(Command::parse(&["-h"]), Ok(Command::Help)));
+        match Command::parse(&["report", "--format=json", "c.toml"]) {
+            Ok(Command::Report(args)) => {
+                assert_eq!(
+                    (args.config.as_str(), args.format),
+                    ("c.toml", Format::Json)
+                )
+            }
+            other => panic!("not a report: {:?}", other),
+        }
+        match Command::parse(&["explain", "c.toml", "AS-FOO", "192.0.2.0/24"]) {
+            Ok(Command::Explain(config, filter, prefix)) => {
+                assert_eq!(
+                    (config, filter, prefix),
+                    ("c.toml".into(), "AS-FOO".into(), "192.0.2.0/24".into())
+                )
+            }
+            other => panic!("not an explanation: {:?}", other),
+        }
+        for args in [
+            &["explain", "c.toml"][..],
+            &["explain", "c.toml", "AS-FOO", "a", "b"],
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Command::parse(args).unwrap_err(),
+                "wrong number of arguments to explain"
+            );
+        }
+        assert_eq!(
+            Command::parse(&["report", "-f", "xml", "c.toml"]).unwrap_err(),
+            "unknown report format xml"
+        );
+    }
+
+    #[test]
+    fn parses_queries() {
+        let q = query(&["query", "AS-FOO"]).unwrap();
+        assert_eq!(q.servers, vec![DEFAULT_SERVER]);
+        assert_eq!(q.sources, vec![DEFAULT_SOURCES]);
+        assert_eq!(
+            (q.style, q.name.as_deref(), q.aggregate),
+            (Style::PrefixList, None, true)
+        );
+        assert_eq!(q.expression(), "AS-FOO");
+
+        let q = query(&[
+            "query",
+            "--host=rr.example.net,127.0.0.1:4343",
+            "-S",
+            "RADB, RIPE",
+            "--format=prefix-set",
+            "-l",
+            "CUSTOMERS",
+            "-n",
+            "AS-FOO",
+            "--",
+            "-weird",
+            "--host",
+        ])
+        .unwrap();
+        assert_eq!(q.servers, vec!["rr.example.net:43", "127.0.0.1:4343"]);
+        assert_eq!(q.sources, vec!["RADB", "RIPE"]);
+        assert_eq!(q.style, Style::PrefixSet);
+        assert_eq!(q.name.as_deref(), Some("CUSTOMERS"));
+        assert!(!q.aggregate);
+        assert_eq!(q.expression(), "AS-FOO OR -weird OR --host");
+
+        let q = query(&[
+            "query",
+            "-h",
+            "2001:db8::1,[2001:db8::2]:4343,[2001:db8::3]",
+            "AS-FOO",
+        ])
+        .unwrap();
+        assert_eq!(
+            q.servers,
+            vec!["[2001:db8::1]:43", "[2001:db8::2]:4343", "[2001:db8::3]:43"]
+        );
+
+        assert_eq!(
+            query(&["query", "-x", "AS-FOO"]).unwrap_err(),
+            "unknown option -x"
+        );
+        assert_eq!(
+            query(&["query", "AS-FOO", "-S"]).unwrap_err(),
+            "-S needs a value"
+        );
+        assert_eq!(
+            query(&["query", "-S", ",", "AS-FOO"]).unwrap_err(),
+            "servers and sources must not be empty"
+        );
+        assert_eq!(
+            query(&["query"]).unwrap_err(),
+            "query needs an object, e.g. AS-FOO"
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use std::{convert::TryFrom, fs::File, io::prelude::*};
+
+use fup::{
+    aggregate::AggPrefix, asn::AsnPolicy, deploy::Platform, expand::ExpandLimits, filter::Filter,
+    filterclass::FilterClass, format::Style, servers::Strategy, AppResult, Map,
+};
+use serde_derive::Deserialize;
+
+use crate::cli::Query;
+
+#[derive(Debug, Deserialize)]
+pub struct RootConfig {
+    pub global: GlobalConfig,
+    pub routers: Vec<RouterConfig>,
+    pub rpki: Option<RpkiConfig>,
+    pub bogons: Option<BogonConfig>,
+    pub asns: Option<AsnConfig>,
+    /// named filters defined as filter expressions over IRR objects and
+    /// prefixes, e.g. "AS-CUSTOMER AND NOT AS-DOWNSTREAM"
+    #[serde(default)]
+    pub filters: Map<String, String>,
+    /// servers to query for some sources instead, e.g. RIPE = "whois.ripe.net:43"
+    #[serde(default)]
+    pub source_servers: Map<String, Servers>,
+    #[serde(default)]
+    pub deploy: DeployConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GlobalConfig {
+    /// irrd server name, or a list of them
+    pub server: Servers,
+    /// how a list of servers is used
+    ///  - "failover" (default): the next server takes over on errors
+    ///  - "merge": every server is asked and the answers combined
+    pub server_strategy: Option<String>,
+    /// where to put the outputted configuration files
+    pub outputdir: String,
+    /// whether to aggregate prefixes
+    pub aggregate: Option<bool>,
+    /// whether to put a timestamp into the outputted configuration files
+    pub timestamps: Option<bool>,
+    /// which source databases to use
+    /// some choices are: radb,afrinic,ripe,ripe-nonauth,bell,apnic,nttcom,
+    /// altdb,panix,risq,nestegg,level3,reach,aoltw,openface,arin,easynet,
+    /// jpirr,host,rgnet,rogers,bboi,tc,canarie
+    pub sources: Vec<String>,
+    /// number of parallel connections to the irrd server
+    pub connections: Option<usize>,
+    /// where as-sets are expanded
+    ///  - "server" (default): the irrd server recurses into nested sets
+    ///  - "client": nested sets are fetched one level at a time
+    pub expansion: Option<String>,
+    /// client expansion: deepest nesting level below a filter's as-set
+    pub max_depth: Option<usize>,
+    /// client expansion: most AS numbers a filter's as-set may expand to
+    pub max_members: Option<usize>,
+    /// whether to query each source separately to track where prefixes come from
+    pub provenance: Option<bool>,
+    /// refuse to write a router's file if a filter lost more than this
+    /// percentage of its entries since the previous run
+    pub max_shrink: Option<u32>,
+    /// refuse to write a router's file if a filter shrank below this many entries
+    pub min_entries: Option<usize>,
+    /// directory to keep the IRR data of each run in
+    pub snapshots: Option<String>,
+    /// shell command run after each output file is written
+    pub hook: Option<String>,
+    /// what a failing hook does to the run (default "fail")
+    pub hook_failure: Option<HookFailure>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouterConfig {
+    pub hostname: String,
+    /// Style of configuation
+    ///  - "prefix-set" (XR)
+    ///  - "prefix-list" (IOS)
+    pub style: String,
+    /// Relevant names of filters for this router
+    pub filters: Vec<FilterEntry>,
+    /// shell command run after the router's file is written instead of the
+    /// global one, none if empty
+    pub hook: Option<String>,
+    pub hook_failure: Option<HookFailure>,
+    /// how to apply the router's file to the router, if at all
+    pub deploy: Option<RouterDeploy>,
+    #[serde(flatten)]
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Servers {
+    One(String),
+    List(Vec<String>),
+}
+
+impl Servers {
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            Servers::One(server) => std::slice::from_ref(server),
+            Servers::List(servers) => servers,
+        }
+    }
+}
+
+/// Global settings that a router or a single filter may override.
+#[derive(Debug, Default, Deserialize)]
+pub struct Overrides {
+    pub server: Option<Servers>,
+    pub sources: Option<Vec<String>>,
+    pub aggregate: Option<bool>,
+    pub timestamps: Option<bool>,
+    pub max_shrink: Option<u32>,
+    pub min_entries: Option<usize>,
+}
+
+/// The settings a filter is generated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Settings<'a> {
+    pub servers: &'a [String],
+    pub sources: &'a [String],
+    pub aggregate: bool,
+    pub timestamps: bool,
+}
+
+/// How much a filter may shrink between runs before its router's file is
+/// left alone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Thresholds {
+    /// percentage of entries
+    pub max_shrink: Option<u32>,
+    pub min_entries: Option<usize>,
+}
+
+impl Thresholds {
+    /// Checks a filter that had `before` entries in the previous output and
+    /// has `after` entries now.
+    pub fn check(&self, before: usize, after: usize) -> Result<(), String> {
+        if after >= before {
+            return Ok(());
+        }
+        if let Some(max) = self.max_shrink {
+            if (before - after) as u64 * 100 > max as u64 * before as u64 {
+                return Err(format!(
+                    "shrank from {} to {} entries, more than {}%",
+                    before, after, max
+                ));
+            }
+        }
+        match self.min_entries {
+            Some(min) if after < min => Err(format!(
+                "shrank from {} to {} entries, below {}",
+                before, after, min
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How routers are connected to for deployment.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DeployConfig {
+    /// ssh command and arguments
+    pub ssh: Vec<String>,
+    /// seconds before a change that is not confirmed is rolled back
+    pub confirm_timeout: u32,
+}
+
+impl Default for DeployConfig {
+    fn default() -> Self {
+        DeployConfig {
+            ssh: vec!["ssh".to_string()],
+            confirm_timeout: 120,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RouterDeploy {
+    /// "xr" or "junos" (NETCONF), or "ios" (CLI)
+    pub platform: String,
+    /// where to connect to (default: the hostname)
+    pub address: Option<String>,
+    /// ssh port (default: 830 for NETCONF, the ssh default for the CLI)
+    pub port: Option<u16>,
+}
+
+impl RouterDeploy {
+    pub fn platform(&self) -> AppResult<Platform> {
+        self.platform.parse()
+    }
+
+    pub fn port(&self, platform: Platform) -> Option<u16> {
+        self.port.or_else(|| platform.subsystem().map(|_| 830))
+    }
+}
+
+/// What a failing hook does to the run.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailure {
+    /// go on with the other routers, then exit with an error
+    Fail,
+    /// print a warning only
+    Warn,
+    /// stop before the next router and exit with an error
+    Abort,
+}
+
+/// The command run after a router's file is written.
+#[derive(Debug, Clone, Copy)]
+pub struct Hook<'a> {
+    pub command: &'a str,
+    pub failure: HookFailure,
+}
+
+/// A filter of a router: the name of what to build it from, or a table that
+/// also names the generated prefix-list or prefix-set.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FilterEntry {
+    Object(String),
+    Table {
+        /// IRR object, policy or named filter
+        object: String,
+        /// output name (default: the object name, rewritten as needed)
+        name: Option<String>,
+        /// output name of the IPv4 entries
+        v4: Option<String>,
+        /// output name of the IPv6 entries
+        v6: Option<String>,
+        /// entries added to the filter, e.g. "192.0.2.0/24 le 26"
+        #[serde(default)]
+        extra_prefixes: Vec<String>,
+        /// entries removed from the filter, also from covering entries
+        #[serde(default)]
+        exclude_prefixes: Vec<String>,
+        #[serde(flatten)]
+        overrides: Box<Overrides>,
+    },
+}
+
+impl FilterEntry {
+    pub fn object(&self) -> &str {
+        match self {
+            FilterEntry::Object(object) | FilterEntry::Table { object, .. } => object,
+        }
+    }
+
+    fn overrides(&self) -> Option<&Overrides> {
+        match self {
+            FilterEntry::Object(_) => None,
+            FilterEntry::Table { overrides, .. } => Some(overrides.as_ref()),
+        }
+    }
+
+    /// Whether `name` is the object or a configured output name of the filter.
+    fn is_named(&self, name: &str) -> bool {
+        match self {
+            FilterEntry::Object(object) => object == name,
+            FilterEntry::Table {
+                object,
+                name: n,
+                v4,
+                v6,
+                ..
+            } => object == name || [n, v4, v6].iter().any(|n| n.as_deref() == Some(name)),
+        }
+    }
+
+    /// Entries to add to the filter, and entries to remove from it.
+    pub fn prefix_changes(&self) -> AppResult<(Vec<AggPrefix>, Vec<AggPrefix>)> {
+        let parse = |list: &[String]| {
+            list.iter()
+                .map(|s| {
+                    s.parse()
+                        .map_err(|e| format!("invalid prefix {}: {}", s, e).into())
+                })
+                .collect::<AppResult<Vec<AggPrefix>>>()
+        };
+        match self {
+            FilterEntry::Object(_) => Ok((vec![], vec![])),
+            FilterEntry::Table {
+                extra_prefixes,
+                exclude_prefixes,
+                ..
+            } => Ok((parse(extra_prefixes)?, parse(exclude_prefixes)?)),
+        }
+    }
+
+    /// Output names for IPv4 and IPv6 entries in `style`.
+    ///
+    /// Configured names must be valid as they are, while the object name is
+    /// rewritten to fit.
+    pub fn names(&self, style: Style) -> AppResult<(String, String)> {
+        let (v4, v6) = match self {
+            FilterEntry::Object(_) => (None, None),
+            FilterEntry::Table { name, v4, v6, .. } => {
+                (v4.as_ref().or(name.as_ref()), v6.as_ref().or(name.as_ref()))
+            }
+        };
+        let name = |given: Option<&String>| match given {
+            Some(name) => style.check_name(name).map(|_| name.clone()),
+            None => Ok(style.sanitize_name(self.object())),
+        };
+        Ok((name(v4)?, name(v6)?))
+    }
+}
+
+impl RootConfig {
+    pub fn load(file_name: &str) -> AppResult<Self> {
+        let mut config_file =
+            File::open(file_name).map_err(|e| format!("failed to open {}: {}", file_name, e))?;
+        let mut file_contents = String::new();
+        config_file
+            .read_to_string(&mut file_contents)
+            .map_err(|e| format!("failed to read config: {}", e))?;
+        Ok(toml::from_str(&file_contents).map_err(|e| format!("failed to parse config: {}", e))?)
+    }
+
+    /// A configuration of the single filter of `fup query`, on a router
+    /// without an output file. Expressions become a named filter.
+    pub fn for_query(query: &Query) -> Self {
+        let object = query.expression();
+        let mut filters = Map::new();
+        if object.contains(char::is_whitespace) || FilterClass::try_from(object.as_str()).is_err() {
+            filters.insert(object.clone(), object.clone());
+        }
+        RootConfig {
+            global: GlobalConfig {
+                server: Servers::List(query.servers.clone()),
+                server_strategy: None,
+                outputdir: String::new(),
+                aggregate: Some(query.aggregate),
+                timestamps: Some(false),
+                sources: query.sources.clone(),
+                connections: None,
+                expansion: None,
+                max_depth: None,
+                max_members: None,
+                provenance: None,
+                max_shrink: None,
+                min_entries: None,
+                snapshots: None,
+                hook: None,
+                hook_failure: None,
+            },
+            routers: vec![RouterConfig {
+                hostname: "query".to_string(),
+                style: query.style.to_string(),
+                filters: vec![FilterEntry::Table {
+                    object,
+                    name: query.name.clone(),
+                    v4: None,
+                    v6: None,
+                    extra_prefixes: Vec::new(),
+                    exclude_prefixes: Vec::new(),
+                    overrides: Box::default(),
+                }],
+                hook: None,
+                hook_failure: None,
+                deploy: None,
+                overrides: Overrides::default(),
+            }],
+            rpki: None,
+            bogons: None,
+            asns: None,
+            filters,
+            source_servers: Map::new(),
+            deploy: DeployConfig::default(),
+        }
+    }
+}
+
+/// Overrides of `filter`, then of `router`.
+fn layers<'a>(router: &'a RouterConfig, filter: &'a FilterEntry) -> Vec<&'a Overrides> {
+    filter
+        .overrides()
+        .into_iter()
+        .chain(Some(&router.overrides))
+        .collect()
+}
+
+impl RouterConfig {
+    pub fn style(&self) -> AppResult<Style> {
+        self.style
+            .parse()
+            .map_err(|e| format!("{}: {}", self.hostname, e).into())
+    }
+
+    /// A router with just `object` as a filter and no settings of its own,
+    /// for objects no router is configured with.
+    pub fn with_filter(object: &str) -> Self {
+        RouterConfig {
+            hostname: object.to_string(),
+            style: Style::PrefixList.to_string(),
+            filters: vec![FilterEntry::Object(object.to_string())],
+            hook: None,
+            hook_failure: None,
+            deploy: None,
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+impl RootConfig {
+    /// Settings of `filter` on `router`: the filter's own, else the router's,
+    /// else the global ones.
+    pub fn settings<'a>(
+        &'a self,
+        router: &'a RouterConfig,
+        filter: &'a FilterEntry,
+    ) -> Settings<'a> {
+        self.layered(&layers(router, filter))
+    }
+
+    /// Shrinkage thresholds of `filter` on `router`, layered like settings.
+    pub fn thresholds(&self, router: &RouterConfig, filter: &FilterEntry) -> Thresholds {
+        let layers = layers(router, filter);
+        Thresholds {
+            max_shrink: layers
+                .iter()
+                .find_map(|o| o.max_shrink)
+                .or(self.global.max_shrink),
+            min_entries: layers
+                .iter()
+                .find_map(|o| o.min_entries)
+                .or(self.global.min_entries),
+        }
+    }
+
+    /// The hook of `router`: its own, else the global one.
+    pub fn hook<'a>(&'a self, router: &'a RouterConfig) -> Option<Hook<'a>> {
+        let command = router
+            .hook
+            .as_deref()
+            .or(self.global.hook.as_deref())
+            .filter(|command| !command.is_empty())?;
+        Some(Hook {
+            command,
+            failure: router
+                .hook_failure
+                .or(self.global.hook_failure)
+                .unwrap_or(HookFailure::Fail),
+        })
+    }
+
+    fn layered<'a>(&'a self, layers: &[&'a Overrides]) -> Settings<'a> {
+        let global = &self.global;
+        Settings {
+            servers: layers
+                .iter()
+                .find_map(|o| o.server.as_ref())
+                .unwrap_or(&global.server)
+                .as_slice(),
+            sources: layers
+                .iter()
+                .find_map(|o| o.sources.as_deref())
+                .unwrap_or(&global.sources),
+            aggregate: layers
+                .iter()
+                .find_map(|o| o.aggregate)
+                .or(global.aggregate)
+                .unwrap_or(true),
+            timestamps: layers
+                .iter()
+                .find_map(|o| o.timestamps)
+                .or(global.timestamps)
+                .unwrap_or(false),
+        }
+    }
+
+    /// The first filter whose object or output name is `name`, and its
+    /// router.
+    pub fn find_filter(&self, name: &str) -> Option<(&RouterConfig, &FilterEntry)> {
+        self.routers
+            .iter()
+            .flat_map(|router| router.filters.iter().map(move |filter| (router, filter)))
+            .find(|(_, filter)| filter.is_named(name))
+    }
+
+    /// `sources` grouped by where they are queried: at their servers in
+    /// `[source_servers]`, else at `servers`. Groups keep the order of
+    /// `sources`, which is the order of preference.
+    pub fn source_routes<'a>(
+        &'a self,
+        servers: &'a [String],
+        sources: &'a [String],
+    ) -> Vec<(&'a [String], String)> {
+        let mut routes: Vec<(&[String], Vec<&str>)> = Vec::new();
+        for source in sources {
+            let at = self
+                .source_servers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(source))
+                .map(|(_, servers)| servers.as_slice())
+                .unwrap_or(servers);
+            match routes.iter_mut().find(|(servers, _)| *servers == at) {
+                Some((_, group)) => group.push(source),
+                None => routes.push((at, vec![source])),
+            }
+        }
+        routes
+            .into_iter()
+            .map(|(servers, sources)| (servers, sources.join(",")))
+            .collect()
+    }
+
+    pub fn asn_policy(&self) -> AppResult<AsnPolicy> {
+        match self.asns.as_ref() {
+            Some(asns) => {
+                AsnPolicy::new(asns.reserved.unwrap_or(true), &asns.include, &asns.exclude)
+            }
+            None => Ok(AsnPolicy::default()),
+        }
+    }
+
+    pub fn named_filters(&self) -> AppResult<Map<&str, Filter>> {
+        self.filters
+            .iter()
+            .map(|(name, expression)| {
+                let filter =
+                    Filter::parse(expression).map_err(|e| format!("filter {}: {}", name, e))?;
+                Ok((name.as_str(), filter))
+            })
+            .collect()
+    }
+}
+
+impl GlobalConfig {
+    pub fn server_strategy(&self) -> AppResult<Strategy> {
+        self.server_strategy
+            .as_deref()
+            .unwrap_or("failover")
+            .parse()
+    }
+
+    pub fn expand_limits(&self) -> ExpandLimits {
+        let defaults = ExpandLimits::default();
+        ExpandLimits {
+            max_depth: self.max_depth.unwrap_or(defaults.max_depth),
+            max_members: self.max_members.unwrap_or(defaults.max_members),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BogonConfig {
+    /// whether to drop bogon prefixes from filters (default true)
+    pub enabled: Option<bool>,
+    /// replaces the built-in IPv4 bogons, e.g. "10.0.0.0/8 le 32"
+    pub v4: Option<Vec<String>>,
+    /// replaces the built-in IPv6 bogons
+    pub v6: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AsnConfig {
+    /// whether to drop reserved, private and documentation AS numbers (default true)
+    pub reserved: Option<bool>,
+    /// AS numbers or ranges to always keep, e.g. "AS64496-AS64511"
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// AS numbers or ranges to always drop, e.g. "AS174"
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RpkiConfig {
+    /// VRP export from rpki-client or Routinator, JSON or CSV
+    pub vrps: String,
+    /// what to do with RPKI invalid routes (default "drop")
+    pub policy: Option<RpkiPolicy>,
+    /// whether to add the VRPs of a filter's AS numbers to the filter
+    pub augment: Option<bool>,
+    /// policy overrides by filter name
+    #[serde(default)]
+    pub filters: Map<String, RpkiPolicy>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RpkiPolicy {
+    /// leave invalid routes out of the filter
+    Drop,
+    /// keep invalid routes, listing them in a comment
+    Annotate,
+    /// don't validate
+    Ignore,
+}
+
+impl RpkiConfig {
+    pub fn policy_for(&self, filter_name: &str) -> RpkiPolicy {
+        self.filters
+            .get(filter_name)
+            .or(self.policy.as_ref())
+            .copied()
+            .unwrap_or(RpkiPolicy::Drop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_thresholds() {
+        let none = Thresholds::default();
+        assert_eq!(none.check(100, 0), Ok(()));
+
+        let shrink = Thresholds {
+            max_shrink: Some(10),
+            min_entries: None,
+        };
+        assert_eq!(shrink.check(100, 90), Ok(()));
+        assert_eq!(shrink.check(100, 120), Ok(()));
+        assert_eq!(
+            shrink.check(100, 89),
+            Err("shrank from 100 to 89 entries, more than 10%".to_string())
+        );
+        // a filter that was empty cannot shrink, and one that empties always does
+        assert_eq!(shrink.check(0, 0), Ok(()));
+        assert!(shrink.check(1, 0).is_err());
+        let all = Thresholds {
+            max_shrink: Some(100),
+            min_entries: None,
+        };
+        assert_eq!(all.check(5, 0), Ok(()));
+
+        let min = Thresholds {
+            max_shrink: None,
+            min_entries: Some(10),
+        };
+        assert_eq!(min.check(20, 10), Ok(()));
+        assert_eq!(
+            min.check(20, 9),
+            Err("shrank from 20 to 9 entries, below 10".to_string())
+        );
+        assert!(min.check(5, 0).is_err());
+        // staying below the minimum is not shrinking
+        assert_eq!(min.check(5, 5), Ok(()));
+        assert_eq!(min.check(0, 0), Ok(()));
+    }
+}
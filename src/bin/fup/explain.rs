@@ -0,0 +1,113 @@
+use std::{convert::TryFrom, iter};
+
+use fup::{
+    aggregate::AggPrefix, filterclass::FilterClass, format::CiscoEntryFmt, irr::parse_prefix,
+    servers::IrrServers, AppResult, Prefix, Set,
+};
+
+use crate::{
+    config::{RootConfig, RouterConfig},
+    generate_for,
+};
+
+/// Prints the chain of IRR objects that puts `prefix` into `filter_name`:
+/// as-set, nested as-sets, AS number, route object and its source, and finally
+/// the filter entry that permits it. The filter is built as `fup run` builds
+/// it, so bogons, RPKI invalid routes and excluded prefixes are left out alike.
+pub fn explain(root_config: &RootConfig, filter_name: &str, prefix: &str) -> AppResult<()> {
+    let target = parse_prefix(prefix)?;
+    let unconfigured;
+    let (router, filter) = match root_config.find_filter(filter_name) {
+        Some(found) => found,
+        None => {
+            unconfigured = RouterConfig::with_filter(filter_name);
+            (&unconfigured, &unconfigured.filters[0])
+        }
+    };
+    let (generated, _) = generate_for(root_config, vec![(router, vec![filter])], None)?;
+    let built = &generated[0].filters[0];
+    let object = built.output.object;
+
+    // each chain of objects with an entry that permits `target`, and whether
+    // that entry is the route itself
+    let extra = (
+        vec![object.to_string(), "extra_prefixes".to_string()],
+        built.output.extra.clone(),
+    );
+    let route = AggPrefix::from_prefix(&target);
+    let chains: Vec<(&Vec<String>, bool)> = built
+        .resolved
+        .members
+        .iter()
+        .chain(iter::once(&extra))
+        .filter(|(_, entries)| entries.iter().any(|entry| entry.matches(&target)))
+        .map(|(chain, entries)| (chain, entries.contains(&route)))
+        .collect();
+    if chains.is_empty() {
+        return Err(format!("{} is not in {}", prefix, filter_name).into());
+    }
+    let mut entries: Vec<&AggPrefix> = built
+        .entries
+        .iter()
+        .filter(|entry| entry.matches(&target))
+        .collect();
+    // without aggregation, a route-set may still permit `target` with a range
+    entries.sort_unstable_by_key(|entry| (entry.max - entry.min, entry.mask));
+    let entry = entries
+        .first()
+        .ok_or_else(|| match built.entries.is_empty() {
+            true => format!("{} is empty, so it is skipped", filter_name),
+            false => format!("{} is in {} but excluded", prefix, filter_name),
+        })?;
+
+    // route objects of `target`, for chains ending in their origin
+    let origin = |chain: &[String]| match chain.last().map(|s| FilterClass::try_from(s.as_str())) {
+        Some(Ok(FilterClass::AutNum(num))) => Some(num),
+        _ => None,
+    };
+    let route_objects = match chains
+        .iter()
+        .any(|(chain, exact)| *exact && origin(chain).is_some())
+    {
+        true => {
+            let settings = &built.output.settings;
+            let mut client = IrrServers::open_routed(
+                &root_config.source_routes(settings.servers, settings.sources),
+                1,
+                root_config.global.server_strategy()?,
+            )?;
+            let targets: Set<Prefix> = iter::once(target).collect();
+            client.resolve_route_objects(&targets)?.remove(&target)
+        }
+        false => None,
+    };
+
+    for (chain, exact) in chains {
+        let mut chain = chain.clone();
+        if let (Some(num), true) = (origin(&chain), exact) {
+            let sources: Vec<&str> = route_objects
+                .iter()
+                .flatten()
+                .filter(|route| route.origin == num)
+                .map(|route| route.source.as_str())
+                .collect();
+            chain.push(match sources.is_empty() {
+                true => format!("route {}/{}", target.0, target.1),
+                false => format!(
+                    "route {}/{} source {}",
+                    target.0,
+                    target.1,
+                    sources.join(",")
+                ),
+            });
+        }
+        chain.push(format!("entry {}", CiscoEntryFmt(entry)));
+        for (depth, object) in chain.iter().enumerate() {
+            match depth {
+                0 => println!("{}", object),
+                _ => println!("{}> {}", "  ".repeat(depth), object),
+            }
+        }
+    }
+    Ok(())
+}
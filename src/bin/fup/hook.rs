@@ -0,0 +1,21 @@
+use std::{
+    io,
+    process::{Command, Stdio},
+};
+
+/// Runs `command` with `sh -c` and `env` added to its environment. Its
+/// output goes to stderr, leaving stdout to the run summary.
+pub fn run(command: &str, env: &[(&str, String)]) -> Result<(), String> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        .status()
+        .map_err(|e| format!("failed to run hook {}: {}", command, e))?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("hook {} failed with {}", command, status)),
+    }
+}
@@ -0,0 +1,1247 @@
+#![forbid(unsafe_code)]
+
+use std::{
+    convert::TryFrom,
+    env, error,
+    fs::{create_dir_all, read_to_string, remove_file, rename, File},
+    io::{self, prelude::*},
+    path::Path,
+    process::exit,
+    rc::Rc,
+    time::Instant,
+};
+
+use fup::{
+    aggregate::{aggregate_entries, subtract_entries, AggPrefix},
+    asn::AsnPolicy,
+    bogon::Bogons,
+    deploy::{self, Change, Channel},
+    expand::fetch_graph,
+    filter::{fetch_filter_sets, Filter, FilterRefs, FilterSets},
+    filterclass::FilterClass,
+    format::{read_filters, CiscoEntryFmt, CiscoPrefixList, CiscoPrefixSet, Style},
+    irrdata::{Irr, IrrData, IrrSnapshot},
+    policy::{exports_to, Export},
+    rpki::{Validity, Vrp, VrpTable},
+    rpsl::{fetch_route_sets, RouteSets},
+    servers::IrrServers,
+    AppResult, Map, Prefix, RouteObject, Set,
+};
+use serde_json::json;
+
+mod cli;
+mod config;
+mod explain;
+mod hook;
+mod snapshot;
+
+use cli::{Command, Query, ReportArgs, RunArgs};
+use config::{
+    FilterEntry, HookFailure, RootConfig, RouterConfig, RouterDeploy, RpkiPolicy, Settings,
+    Thresholds,
+};
+use snapshot::{entry_string, FilterSnapshot, Report, Snapshot};
+
+/// IRR servers and the sources queried there.
+type Database<'a> = (&'a [String], &'a [String]);
+
+/// A chain of objects from a filter down to a member, e.g. as-set, nested
+/// as-set and AS number, and the entries before aggregation it contributes.
+type Member = (Vec<String>, Vec<AggPrefix>);
+
+/// A filter resolved from IRR data, before aggregation.
+struct Resolved {
+    members: Vec<Member>,
+    /// extra comment lines
+    notes: Vec<String>,
+}
+
+impl Resolved {
+    fn entries(&self) -> impl Iterator<Item = &AggPrefix> {
+        self.members.iter().flat_map(|(_, entries)| entries.iter())
+    }
+}
+
+/// A filter as configured on a router.
+struct Output<'a> {
+    object: &'a str,
+    /// IPv4 and IPv6 names
+    names: (String, String),
+    extra: Vec<AggPrefix>,
+    exclude: Vec<AggPrefix>,
+    settings: Settings<'a>,
+    thresholds: Thresholds,
+}
+
+impl<'a> Output<'a> {
+    /// Family and name of each prefix-list or prefix-set the output is
+    /// written as; IOS keeps IPv4 and IPv6 prefix-lists apart.
+    fn keys(&self, style: Style) -> Vec<(&'static str, &str)> {
+        let (v4, v6) = (self.names.0.as_str(), self.names.1.as_str());
+        match style {
+            Style::PrefixList => vec![("ip", v4), ("ipv6", v6)],
+            Style::PrefixSet if v4 == v6 => vec![("", v4)],
+            Style::PrefixSet => vec![("", v4), ("", v6)],
+        }
+    }
+
+    /// `entries` of the output by the family and name they are written under.
+    fn split(
+        &self,
+        style: Style,
+        entries: &[AggPrefix],
+    ) -> Vec<((&'static str, &str), Vec<AggPrefix>)> {
+        match self.keys(style).as_slice() {
+            [(family, name)] if style == Style::PrefixSet => {
+                vec![((family, name), entries.to_vec())]
+            }
+            [v4_key, v6_key, ..] => {
+                let (v4, v6) = entries
+                    .iter()
+                    .cloned()
+                    .partition(|entry| entry.prefix.is_ipv4());
+                vec![(*v4_key, v4), (*v6_key, v6)]
+            }
+            _ => unreachable!("an output has one or two names"),
+        }
+    }
+
+    fn database(&self) -> Database<'a> {
+        (self.settings.servers, self.settings.sources)
+    }
+
+    /// What the output's entries depend on.
+    fn key(&self) -> (Settings<'a>, &'a str, &[AggPrefix], &[AggPrefix]) {
+        (self.settings, self.object, &self.extra, &self.exclude)
+    }
+}
+
+/// A filter of a router, what it was resolved to and its entries.
+struct Built<'a> {
+    output: Output<'a>,
+    resolved: Rc<Resolved>,
+    entries: Vec<AggPrefix>,
+}
+
+/// The filters of a router, and its configuration.
+struct Generated<'a> {
+    router: &'a RouterConfig,
+    style: Style,
+    filters: Vec<Built<'a>>,
+    config: String,
+}
+
+impl<'a> Generated<'a> {
+    /// Filters that shrank beyond their thresholds since `previous`, the
+    /// configuration last written for the router.
+    fn shrunk(&self, previous: &str) -> Vec<String> {
+        let previous = read_filters(previous);
+        self.filters
+            .iter()
+            .filter_map(
+                |Built {
+                     output, entries, ..
+                 }| {
+                    let before = output
+                        .keys(self.style)
+                        .into_iter()
+                        .filter_map(|(family, name)| previous.get(&(family, name.to_string())))
+                        .map(Vec::len)
+                        .sum();
+                    let e = output.thresholds.check(before, entries.len()).err()?;
+                    Some(format!("{}: {}", output.object, e))
+                },
+            )
+            .collect()
+    }
+
+    /// Names of the prefix-lists or prefix-sets whose entries differ from
+    /// those in `previous`, including ones no longer configured.
+    fn changed_filters(&self, previous: &str) -> Vec<String> {
+        let filters = self.filters.iter().flat_map(
+            |Built {
+                 output, entries, ..
+             }| output.split(self.style, entries),
+        );
+        changed_filters(previous, filters)
+    }
+
+    /// Entries of each prefix-list or prefix-set, by name.
+    fn by_name(&self) -> Vec<(&str, Vec<AggPrefix>)> {
+        let mut filters: Vec<(&str, Vec<AggPrefix>)> = Vec::new();
+        for Built {
+            output, entries, ..
+        } in self.filters.iter()
+        {
+            for ((_, name), entries) in output.split(self.style, entries) {
+                match filters.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, list)) => list.extend(entries),
+                    None => filters.push((name, entries)),
+                }
+            }
+        }
+        filters
+    }
+
+    /// Applies the configuration to the router with `deploy`.
+    fn deploy(&self, root_config: &RootConfig, deploy: &RouterDeploy) -> AppResult<()> {
+        let platform = deploy.platform()?;
+        let address = deploy.address.as_deref().unwrap_or(&self.router.hostname);
+        let port = deploy.port(platform);
+        let filters = self.by_name();
+        let change = Change {
+            config: &self.config,
+            filters: &filters,
+        };
+        deploy::apply(
+            platform,
+            |subsystem| Channel::ssh(&root_config.deploy.ssh, address, port, subsystem),
+            &change,
+            root_config.deploy.confirm_timeout,
+        )
+    }
+
+    fn snapshot(&self) -> Vec<FilterSnapshot> {
+        let strings = |entries: &[AggPrefix]| entries.iter().map(entry_string).collect();
+        self.filters
+            .iter()
+            .map(|built| {
+                let mut members: Vec<(Vec<String>, Vec<String>)> = built
+                    .resolved
+                    .members
+                    .iter()
+                    .map(|(chain, entries)| (chain.clone(), strings(entries)))
+                    .collect();
+                if !built.output.extra.is_empty() {
+                    let chain = vec![
+                        built.output.object.to_string(),
+                        "extra_prefixes".to_string(),
+                    ];
+                    members.push((chain, strings(&built.output.extra)));
+                }
+                FilterSnapshot {
+                    object: built.output.object.to_string(),
+                    names: built.output.names.clone(),
+                    members,
+                    entries: strings(&built.entries),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The comment put on every generated filter.
+fn comment() -> String {
+    format!("Generated by {}-{}", fup::CLIENT, fup::VERSION)
+}
+
+fn now() -> time::OffsetDateTime {
+    time::OffsetDateTime::try_now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+}
+
+/// The local time, e.g. 2021-03-01T12:00:00+0100.
+fn timestamp() -> String {
+    now().format("%FT%T%z")
+}
+
+/// `config` without the generation time in its comments.
+fn without_timestamps(config: &str) -> Vec<&str> {
+    let comment = comment();
+    let timestamped = format!("{} at ", comment);
+    config
+        .lines()
+        .map(|line| match line.find(&timestamped) {
+            Some(at) => &line[..at + comment.len()],
+            None => line,
+        })
+        .collect()
+}
+
+/// Names of the prefix-lists or prefix-sets in `filters`, entries by family
+/// and name, whose entries differ from those in `previous`, including ones no
+/// longer there.
+fn changed_filters<'f>(
+    previous: &str,
+    filters: impl Iterator<Item = ((&'static str, &'f str), Vec<AggPrefix>)>,
+) -> Vec<String> {
+    let mut previous = read_filters(previous);
+    let mut changed = Vec::new();
+    for ((family, name), entries) in filters {
+        let before = previous
+            .remove(&(family, name.to_string()))
+            .unwrap_or_default();
+        if before != entries {
+            changed.push(name.to_string());
+        }
+    }
+    changed.extend(previous.into_iter().map(|((_, name), _)| name));
+    changed.sort_unstable();
+    changed.dedup();
+    changed
+}
+
+/// Puts `previous` back in `filename`, or removes the file if there was none.
+fn restore(filename: &str, previous: Option<&str>) -> AppResult<()> {
+    match previous {
+        Some(previous) => {
+            let temp_filename = format!("{}.tmp", filename);
+            File::create(&temp_filename)
+                .and_then(|mut file| file.write_all(previous.as_bytes()))
+                .map_err(|e| format!("failed to write {}: {}", temp_filename, e))?;
+            rename(&temp_filename, filename)
+                .map_err(|e| format!("rename {} to {}: {}", temp_filename, filename, e))?;
+        }
+        None => {
+            remove_file(filename).map_err(|e| format!("failed to remove {}: {}", filename, e))?
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let progname = Path::new(&args[0]).file_name().unwrap().to_string_lossy();
+    let command = match Command::parse(&args[1..]) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}\n{}", e, cli::usage(&progname));
+            exit(1);
+        }
+    };
+    let result = match command {
+        Command::Run(args) => run(&args),
+        Command::Report(args) => report(&args),
+        Command::Explain(config, filter, prefix) => RootConfig::load(&config)
+            .and_then(|root_config| explain::explain(&root_config, &filter, &prefix)),
+        Command::Query(query) => run_query(&query),
+        Command::Help => writeln!(io::stdout(), "{}", cli::usage(&progname)).map_err(Into::into),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        exit(1);
+    }
+}
+
+fn run(args: &RunArgs) -> AppResult<()> {
+    let root_config = RootConfig::load(&args.config)?;
+    create_dir_all(&root_config.global.outputdir).map_err(|e| {
+        format!(
+            "failed to create output dir {}: {}",
+            &root_config.global.outputdir, e
+        )
+    })?;
+
+    let snapshot_path = snapshot::path(&root_config.global.outputdir);
+    // routers that are not written keep their previous snapshot, like their files
+    let mut snapshot = Snapshot::load(&snapshot_path)?.unwrap_or_default();
+    snapshot.time = timestamp();
+    let mut changed = Vec::new();
+    let mut unchanged = Vec::new();
+    let mut refused = Vec::new();
+    let mut failed_hooks = Vec::new();
+    let mut failed_deploys = Vec::new();
+    let mut aborted = false;
+    let irr_snapshot = match args.from_snapshot.as_deref() {
+        Some(path) => Some(IrrSnapshot::load(path)?),
+        None => None,
+    };
+    let (routers, recorded) = generate(&root_config, irr_snapshot.as_ref())?;
+    if let (Some(dir), None) = (root_config.global.snapshots.as_deref(), &irr_snapshot) {
+        let time = now();
+        let path = IrrSnapshot::new(time.format("%FT%T%z"), recorded)
+            .save(dir, &time.format("%Y%m%dT%H%M%S"))?;
+        eprintln!("Saved IRR data to {}", path);
+    }
+    for generated in routers {
+        let hostname = &generated.router.hostname;
+        let output_filename = format!("{}/{}.txt", root_config.global.outputdir, hostname);
+        let previous = match read_to_string(&output_filename) {
+            Ok(previous) => Some(previous),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("failed to read {}: {}", output_filename, e).into()),
+        };
+        let previous = previous.as_deref();
+        if previous.map(without_timestamps) == Some(without_timestamps(&generated.config)) {
+            snapshot
+                .routers
+                .insert(hostname.clone(), generated.snapshot());
+            eprintln!("Unchanged {}", output_filename);
+            unchanged.push(hostname.as_str());
+            continue;
+        }
+        let existed = previous.is_some();
+        let previous = previous.unwrap_or_default();
+        if !args.allow_shrink {
+            let shrunk = generated.shrunk(previous);
+            for e in shrunk.iter() {
+                eprintln!("Error: {}: {}", hostname, e);
+            }
+            if !shrunk.is_empty() {
+                refused.push(hostname.as_str());
+                continue;
+            }
+        }
+        let temp_filename = format!("{}.tmp", &output_filename);
+        let mut output_file = File::create(&temp_filename)
+            .map_err(|e| format!("failed to create {}: {}", temp_filename, e))?;
+        output_file
+            .write_all(generated.config.as_bytes())
+            .map_err(|e| format!("failed to write to output file: {}", e))?;
+        // the file is only put in place once the router has it, so that
+        // the next run tries again
+        let mut deployed = false;
+        if let (Some(deploy), false) = (generated.router.deploy.as_ref(), args.no_deploy) {
+            if let Err(e) = generated.deploy(&root_config, deploy) {
+                eprintln!("Error: {}: deploy failed: {}", hostname, e);
+                remove_file(&temp_filename)
+                    .map_err(|e| format!("failed to remove {}: {}", temp_filename, e))?;
+                failed_deploys.push(hostname.as_str());
+                continue;
+            }
+            eprintln!("Deployed {}", hostname);
+            deployed = true;
+        }
+        rename(&temp_filename, &output_filename)
+            .map_err(|e| format!("rename {} to {}: {}", temp_filename, output_filename, e))?;
+        eprintln!("Wrote {}", output_filename);
+        let changed_filters = generated.changed_filters(previous);
+        let mut hook_failed = false;
+        if let (Some(hook), false) = (root_config.hook(generated.router), args.no_hooks) {
+            let counts: Vec<String> = generated
+                .by_name()
+                .iter()
+                .map(|(name, entries)| format!("{}={}", name, entries.len()))
+                .collect();
+            let env = [
+                ("FUP_HOSTNAME", hostname.clone()),
+                ("FUP_FILE", output_filename.clone()),
+                ("FUP_STYLE", generated.style.to_string()),
+                ("FUP_CHANGED_FILTERS", changed_filters.join(" ")),
+                ("FUP_ENTRIES", counts.join(" ")),
+            ];
+            if let Err(e) = hook::run(hook.command, &env) {
+                match hook.failure {
+                    HookFailure::Warn => eprintln!("Warning: {}: {}", hostname, e),
+                    HookFailure::Fail | HookFailure::Abort => {
+                        eprintln!("Error: {}: {}", hostname, e);
+                        failed_hooks.push(hostname.as_str());
+                        hook_failed = true;
+                        aborted = hook.failure == HookFailure::Abort;
+                    }
+                }
+            }
+        }
+        if hook_failed && !deployed {
+            // the previous file goes back, so that the next run runs the
+            // hook again; a deployed router keeps its file, as it runs it
+            restore(&output_filename, existed.then_some(previous))?;
+            match existed {
+                true => eprintln!("Restored {}", output_filename),
+                false => eprintln!("Removed {}", output_filename),
+            }
+        } else {
+            snapshot
+                .routers
+                .insert(hostname.clone(), generated.snapshot());
+            changed.push(json!({
+                "hostname": hostname,
+                "file": output_filename,
+                "filters": changed_filters,
+            }));
+        }
+        if aborted {
+            break;
+        }
+    }
+    snapshot.save(&snapshot_path)?;
+    println!(
+        "{}",
+        json!({
+            "changed": changed,
+            "unchanged": unchanged,
+            "refused": refused,
+            "failed_hooks": failed_hooks,
+            "failed_deploys": failed_deploys,
+        })
+    );
+
+    let mut errors = Vec::new();
+    if !refused.is_empty() {
+        errors.push(format!(
+            "filters shrank beyond their thresholds, left {} unchanged \
+             (--allow-shrink writes them anyway)",
+            refused.join(", ")
+        ));
+    }
+    if !failed_hooks.is_empty() {
+        errors.push(format!("hooks failed for {}", failed_hooks.join(", ")));
+    }
+    if !failed_deploys.is_empty() {
+        errors.push(format!(
+            "deploy failed for {}, left their files unchanged",
+            failed_deploys.join(", ")
+        ));
+    }
+    if aborted {
+        errors.push("stopped before the remaining routers".to_string());
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("; ").into()),
+    }
+}
+
+/// Prints what changed since the snapshot of the last run.
+fn report(args: &ReportArgs) -> AppResult<()> {
+    let root_config = RootConfig::load(&args.config)?;
+    let snapshot = Snapshot::load(&snapshot::path(&root_config.global.outputdir))?;
+    let routers: Vec<(String, Vec<FilterSnapshot>)> = generate(&root_config, None)?
+        .0
+        .iter()
+        .map(|generated| (generated.router.hostname.clone(), generated.snapshot()))
+        .collect();
+    let report = Report::new(snapshot.as_ref(), &routers).format(args.format)?;
+    io::stdout()
+        .write_all(report.as_bytes())
+        .map_err(|e| format!("failed to write to stdout: {}", e).into())
+}
+
+/// Prints the filter of `query` to stdout.
+fn run_query(query: &Query) -> AppResult<()> {
+    let root_config = RootConfig::for_query(query);
+    for generated in generate(&root_config, None)?.0 {
+        io::stdout()
+            .write_all(generated.config.as_bytes())
+            .map_err(|e| format!("failed to write to stdout: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Resolves, aggregates and formats the filters of every router, in the
+/// order of the configuration, with IRR data from the servers or else from
+/// `irr_snapshot`. Also returns the IRR data fetched from the servers.
+fn generate<'a>(
+    root_config: &'a RootConfig,
+    irr_snapshot: Option<&IrrSnapshot>,
+) -> AppResult<(Vec<Generated<'a>>, Vec<IrrData>)> {
+    let routers = root_config
+        .routers
+        .iter()
+        .map(|router| (router, router.filters.iter().collect()))
+        .collect();
+    generate_for(root_config, routers, irr_snapshot)
+}
+
+/// Like `generate`, for the given filters of the given routers only.
+fn generate_for<'a>(
+    root_config: &'a RootConfig,
+    routers: Vec<(&'a RouterConfig, Vec<&'a FilterEntry>)>,
+    irr_snapshot: Option<&IrrSnapshot>,
+) -> AppResult<(Vec<Generated<'a>>, Vec<IrrData>)> {
+    // output style and filters of each router
+    let mut router_filters: Vec<(Style, Vec<Output>)> = Vec::new();
+    for (router, filters) in routers.iter() {
+        let style = router.style()?;
+        let mut taken: Set<(&str, String)> = Set::new();
+        let mut outputs = Vec::new();
+        for filter in filters.iter() {
+            let context = |e| format!("{}: {}: {}", router.hostname, filter.object(), e);
+            let (v4, v6) = filter.names(style).map_err(context)?;
+            let (extra, exclude) = filter.prefix_changes().map_err(context)?;
+            let output = Output {
+                object: filter.object(),
+                names: (v4, v6),
+                extra,
+                exclude,
+                settings: root_config.settings(router, filter),
+                thresholds: root_config.thresholds(router, filter),
+            };
+            for (family, name) in output.keys(style) {
+                if !taken.insert((family, name.to_string())) {
+                    return Err(
+                        format!("{}: duplicate {} name {}", router.hostname, style, name).into(),
+                    );
+                }
+            }
+            outputs.push(output);
+        }
+        router_filters.push((style, outputs));
+    }
+
+    let named_filters = root_config.named_filters()?;
+    let outputs = || {
+        router_filters
+            .iter()
+            .flat_map(|(_, outputs)| outputs.iter())
+    };
+    for output in outputs().filter(|output| !named_filters.contains_key(output.object)) {
+        FilterClass::try_from(output.object)
+            .map_err(|e| format!("failed to parse filter name: {}", e))?;
+    }
+
+    root_config.global.server_strategy()?;
+    let asn_policy = root_config.asn_policy()?;
+    let bogons = match root_config.bogons.as_ref() {
+        Some(bogons) if !bogons.enabled.unwrap_or(true) => Bogons::none(),
+        Some(bogons) => Bogons::with_overrides(bogons.v4.as_deref(), bogons.v6.as_deref())?,
+        None => Bogons::default(),
+    };
+
+    eprintln!("{} version {}", fup::CLIENT, fup::VERSION);
+    let vrps = match root_config.rpki.as_ref() {
+        Some(rpki) => {
+            let vrps = VrpTable::load(&rpki.vrps)?;
+            eprintln!("Loaded {} VRPs from {}.", vrps.len(), rpki.vrps);
+            Some(vrps)
+        }
+        None => None,
+    };
+
+    // filters by the server and sources they are resolved with
+    let mut databases: Map<Database, Set<&str>> = Map::new();
+    for output in outputs() {
+        databases
+            .entry(output.database())
+            .or_default()
+            .insert(output.object);
+    }
+    let mut databases: Vec<_> = databases.into_iter().collect();
+    databases.sort_unstable_by_key(|(database, _)| *database);
+    // entries and notes of each filter per database, before aggregation
+    let mut results: Map<(Database, &str), Rc<Resolved>> = Map::new();
+    let mut recorded = Vec::new();
+    for (database, filters) in databases {
+        let (servers, sources) = database;
+        let mut irr = match irr_snapshot {
+            Some(snapshot) => Irr::Replay(snapshot.database(servers, sources)?),
+            None => Irr::Live(
+                IrrServers::open_routed(
+                    &root_config.source_routes(servers, sources),
+                    root_config.global.connections.unwrap_or(1),
+                    root_config.global.server_strategy()?,
+                )?,
+                Box::new(IrrData::new(servers, sources)),
+            ),
+        };
+        let resolved = resolve(
+            root_config,
+            &mut irr,
+            &filters,
+            &named_filters,
+            &asn_policy,
+            &bogons,
+            vrps.as_ref(),
+        )?;
+        results.extend(
+            resolved
+                .into_iter()
+                .map(|(filter, result)| ((database, filter), Rc::new(result))),
+        );
+        recorded.extend(irr.into_data());
+    }
+
+    // the entries of each distinct output, with additions and exclusions
+    // applied before aggregating so that excluded prefixes stay out
+    let mut agg_count = 0;
+    let mut nonagg_count = 0;
+    let mut entry_lists = Map::new();
+    for output in outputs() {
+        let key = output.key();
+        if entry_lists.contains_key(&key) {
+            continue;
+        }
+        let entries = results[&(output.database(), output.object)].entries();
+        let entries = entries.chain(output.extra.iter());
+        let mut entry_list = subtract_entries(entries.cloned().collect(), &output.exclude);
+        if entry_list.is_empty() {
+            let reason = if output.exclude.is_empty() {
+                ""
+            } else {
+                " after exclusions"
+            };
+            eprintln!("Warning: {} is empty{}, skipping", output.object, reason);
+        } else if output.settings.aggregate {
+            nonagg_count += entry_list.len();
+            entry_list = aggregate_entries(entry_list);
+            agg_count += entry_list.len();
+        }
+        entry_list.sort_unstable();
+        entry_list.dedup();
+        entry_lists.insert(key, entry_list);
+    }
+
+    if outputs().any(|output| output.settings.aggregate) {
+        eprintln!(
+            "Aggregated {} prefixes into {} entries.",
+            nonagg_count, agg_count
+        );
+    }
+
+    let comment = comment();
+    let timestamped = format!("{} at {}", comment, timestamp());
+
+    let mut configs = Vec::new();
+    let mut filter_entries = Vec::new();
+    for (style, router_outputs) in router_filters.iter() {
+        let mut config = String::new();
+        let mut built = Vec::new();
+        for output in router_outputs.iter() {
+            let resolved = &results[&(output.database(), output.object)];
+            let entries = &entry_lists[&output.key()];
+            built.push((Rc::clone(resolved), entries.clone()));
+            if entries.is_empty() {
+                continue;
+            }
+            let notes = &resolved.notes;
+            let comment = match output.settings.timestamps {
+                true => &timestamped,
+                false => &comment,
+            };
+            let names = (output.names.0.as_str(), output.names.1.as_str());
+            let filter = match style {
+                Style::PrefixSet => CiscoPrefixSet(names, comment, entries, notes).to_string(),
+                Style::PrefixList => CiscoPrefixList(names, comment, entries, notes).to_string(),
+            };
+            config.push_str(&filter);
+        }
+        if *style == Style::PrefixList {
+            config.push_str("end\n");
+        }
+        configs.push(config);
+        filter_entries.push(built);
+    }
+
+    let generated = routers
+        .into_iter()
+        .map(|(router, _)| router)
+        .zip(router_filters)
+        .zip(configs.into_iter().zip(filter_entries))
+        .map(
+            |((router, (style, outputs)), (config, entries))| Generated {
+                router,
+                style,
+                filters: outputs
+                    .into_iter()
+                    .zip(entries)
+                    .map(|(output, (resolved, entries))| Built {
+                        output,
+                        resolved,
+                        entries,
+                    })
+                    .collect(),
+                config,
+            },
+        )
+        .collect();
+    Ok((generated, recorded))
+}
+
+/// Resolves `filters` with one list of IRR servers and sources, returning
+/// the entries (before aggregation) and notes of each.
+fn resolve<'a>(
+    root_config: &'a RootConfig,
+    client: &mut Irr,
+    filters: &Set<&'a str>,
+    named_filters: &Map<&'a str, Filter>,
+    asn_policy: &AsnPolicy,
+    bogons: &Bogons,
+    vrps: Option<&VrpTable>,
+) -> AppResult<Map<&'a str, Resolved>> {
+    let queries: Result<Set<FilterClass>, Box<dyn error::Error>> = filters
+        .iter()
+        .filter(|s| !named_filters.contains_key(*s))
+        .map(|s| FilterClass::try_from(*s))
+        .collect();
+
+    let queries = queries.map_err(|e| format!("failed to parse filter name: {}", e))?;
+
+    let mut as_set_queries: Set<&str> = Default::default();
+    let mut route_set_queries: Set<&str> = Default::default();
+    let mut filter_set_queries: Set<&str> = Default::default();
+    let mut autnum_queries: Set<u32> = Default::default();
+    let mut policy_queries: Set<(u32, u32)> = Default::default();
+
+    queries.into_iter().for_each(|q| {
+        match q {
+            FilterClass::AsSet(name) => as_set_queries.insert(name),
+            FilterClass::RouteSet(name) => route_set_queries.insert(name),
+            FilterClass::FilterSet(name) => filter_set_queries.insert(name),
+            FilterClass::AutNum(num) => autnum_queries.insert(num),
+            FilterClass::Policy(from, to) => policy_queries.insert((from, to)),
+        };
+    });
+
+    let start_time = Instant::now();
+    let (servers, sources) = client.database();
+    let several_servers =
+        root_config.source_routes(servers, sources).len() > 1 || servers.len() > 1;
+    if let Irr::Replay(_) = client {
+        eprintln!("Using snapshot data of sources {}.", sources.join(","));
+    }
+    for (_, addr, sources) in client.peers() {
+        eprintln!(
+            "Connected to {} ({} connections, sources {}).",
+            addr,
+            client.size(),
+            sources
+        );
+    }
+
+    // what the aut-nums of policy filters export to us
+    let policy_autnums: Vec<String> = policy_queries
+        .iter()
+        .map(|(from, _)| format!("AS{}", from))
+        .collect();
+    let autnum_objects = client
+        .resolve_objects(
+            "aut-num",
+            &policy_autnums.iter().map(String::as_str).collect(),
+        )
+        .map_err(|e| format!("failed to resolve aut-nums: {}", e))?;
+    let policies: Map<(u32, u32), Result<Vec<Export>, String>> = policy_queries
+        .iter()
+        .map(|&(from, to)| {
+            let exports = match autnum_objects.get(format!("AS{}", from).as_str()) {
+                Some(object) => exports_to(object, to).map_err(|e| e.to_string()),
+                None => Err(format!("aut-num AS{} not found", from)),
+            };
+            ((from, to), exports)
+        })
+        .collect();
+    let mut filter_refs = FilterRefs::default();
+    for export in policies.values().flatten().flatten() {
+        export.filter.references(&mut filter_refs);
+    }
+    for filter in named_filters.values() {
+        filter.references(&mut filter_refs);
+    }
+    filter_set_queries.extend(filter_refs.filter_sets.iter().copied());
+
+    let filter_sets =
+        fetch_filter_sets(&filter_set_queries, |sets| client.resolve_filter_sets(sets))
+            .map_err(|e| format!("failed to resolve filter-sets: {}", e))?;
+    for filter in filter_sets.values().flatten() {
+        filter.references(&mut filter_refs);
+    }
+    route_set_queries.extend(filter_refs.route_sets);
+    as_set_queries.extend(filter_refs.as_sets);
+    autnum_queries.extend(filter_refs.autnums);
+
+    let route_sets = fetch_route_sets(&route_set_queries, |sets| client.resolve_route_sets(sets))
+        .map_err(|e| format!("failed to resolve route-sets: {}", e))?;
+    // AS numbers and as-sets in route-sets stand for the routes they originate
+    let (route_set_autnums, route_set_as_sets) = RouteSets::references(&route_sets);
+    as_set_queries.extend(route_set_as_sets);
+    autnum_queries.extend(route_set_autnums);
+    // sets from each as-set down to the one listing an AS number, if known
+    let mut as_set_paths: Map<&str, Map<u32, Vec<String>>> = Map::new();
+    let mut as_set_members: Map<&str, Vec<u32>> = match root_config.global.expansion.as_deref() {
+        None | Some("server") => client
+            .resolve_as_sets(&as_set_queries)
+            .map_err(|e| format!("failed to resolve as-sets: {}", e))?,
+        Some("client") => {
+            let limits = root_config.global.expand_limits();
+            let graph = fetch_graph(&as_set_queries, limits.max_depth, |sets| {
+                client.resolve_set_members(sets)
+            })
+            .map_err(|e| format!("failed to resolve as-sets: {}", e))?;
+            as_set_queries
+                .iter()
+                .map(|name| {
+                    let expansion = graph.expand(name, &limits);
+                    for cycle in expansion.cycles.iter() {
+                        eprintln!("Warning: {}: loop {}", name, cycle.join(" > "));
+                    }
+                    if let Some(violation) = &expansion.violation {
+                        eprintln!("Warning: {}: {}", name, violation);
+                        for line in expansion.describe_contributions(name) {
+                            eprintln!("{}", line);
+                        }
+                        (*name, Vec::new())
+                    } else {
+                        as_set_paths.insert(name, expansion.paths);
+                        (*name, expansion.autnums)
+                    }
+                })
+                .collect()
+        }
+        Some(other) => return Err(format!("Unknown expansion: {}", other).into()),
+    };
+    let mut set_names: Vec<&str> = as_set_members.keys().copied().collect();
+    set_names.sort_unstable();
+    for name in set_names {
+        let members = as_set_members.get_mut(name).expect("BUG: missing as-set");
+        let (kept, removed) = asn_policy.apply(members);
+        if !removed.is_empty() {
+            let removed: Vec<String> = removed
+                .iter()
+                .map(|(num, reason)| format!("AS{} ({})", num, reason))
+                .collect();
+            eprintln!("Removed from {}: {}", name, removed.join(", "));
+        }
+        *members = kept;
+    }
+    autnum_queries.extend(as_set_members.values().flatten());
+    let autnum_routes: Option<Map<u32, Vec<RouteObject>>> =
+        if root_config.global.provenance.unwrap_or(false) {
+            Some(
+                client
+                    .resolve_autnum_routes(&autnum_queries)
+                    .map_err(|e| format!("failed to resolve autnums: {}", e))?,
+            )
+        } else {
+            None
+        };
+    let autnum_prefixes: Map<u32, Vec<Prefix>> = match autnum_routes.as_ref() {
+        Some(autnum_routes) => autnum_routes
+            .iter()
+            .map(|(num, routes)| {
+                let prefixes: Set<Prefix> = routes.iter().map(|route| route.prefix).collect();
+                (*num, prefixes.into_iter().collect())
+            })
+            .collect(),
+        None => client
+            .resolve_autnums(&autnum_queries)
+            .map_err(|e| format!("failed to resolve autnums: {}", e))?,
+    };
+
+    // the sources of each route, for entries of route-sets and filter
+    // expressions
+    let mut route_sources: Map<Prefix, Set<&str>> = Map::new();
+    for route in autnum_routes.iter().flat_map(Map::values).flatten() {
+        route_sources
+            .entry(route.prefix)
+            .or_default()
+            .insert(route.source.as_str());
+    }
+    // the routes that are not RPKI invalid, for filters whose entries come
+    // from route-sets and filter expressions rather than origins
+    let rpki_split = vrps.map(|vrps| vrps.split_invalid(&autnum_prefixes));
+
+    eprintln!(
+        "{} objects {} in {:.2} s.",
+        as_set_queries.len()
+            + route_sets.len()
+            + filter_sets.len()
+            + policies.len()
+            + autnum_queries.len(),
+        match client {
+            Irr::Live(..) => "downloaded",
+            Irr::Replay(_) => "read",
+        },
+        start_time.elapsed().as_secs_f32()
+    );
+    for (server, e) in client.failures() {
+        eprintln!("Warning: {}: {}", server, e);
+    }
+    if several_servers {
+        for (query, answered) in client.answers() {
+            eprintln!("{} answered by {}", query, answered.join(", "));
+        }
+    }
+
+    let mut results: Map<&str, Resolved> = Map::new();
+    filters.iter().for_each(|&filter_name| {
+        let mut prefix_set: Set<Prefix> = Default::default();
+        let mut notes: Vec<String> = Vec::new();
+        let rpki_policy = root_config
+            .rpki
+            .as_ref()
+            .map(|rpki| rpki.policy_for(filter_name))
+            .unwrap_or(RpkiPolicy::Ignore);
+        // AS numbers whose routes make up the filter
+        let origins: Vec<u32> = match named_filters.contains_key(filter_name) {
+            true => vec![],
+            false => match FilterClass::try_from(filter_name).expect("BUG: invalid filter") {
+                FilterClass::AsSet(name) => as_set_members[name].clone(),
+                FilterClass::AutNum(num) => vec![num],
+                _ => vec![],
+            },
+        };
+        // entries of route-sets, filter-sets, policies and named filters,
+        // with the objects they come from, given the routes of AS numbers
+        let entries_from = |autnums: &Map<u32, Vec<Prefix>>, warnings: &mut Vec<String>| {
+            let route_sets = RouteSets {
+                route_sets: &route_sets,
+                as_sets: &as_set_members,
+                autnums,
+            };
+            let resolver = FilterSets {
+                filter_sets: &filter_sets,
+                route_sets,
+            };
+            set_entries(filter_name, named_filters, &policies, &resolver, warnings)
+        };
+        let mut warnings = Vec::new();
+        let set_entries = match (rpki_policy, rpki_split.as_ref()) {
+            (RpkiPolicy::Drop, Some((kept, _))) => entries_from(kept, &mut warnings),
+            (RpkiPolicy::Annotate, Some((kept, invalid))) => {
+                let entries = entries_from(&autnum_prefixes, &mut warnings);
+                let valid: Set<AggPrefix> = entries_from(kept, &mut Vec::new())
+                    .into_iter()
+                    .map(|(_, entry)| entry)
+                    .collect();
+                for (_, entry) in entries.iter().filter(|(_, entry)| !valid.contains(entry)) {
+                    let invalid_origins = invalid.get(&(entry.prefix, entry.mask));
+                    for num in invalid_origins.into_iter().flatten() {
+                        notes.push(format!(
+                            "RPKI invalid: {}/{} origin AS{}",
+                            entry.prefix, entry.mask, num
+                        ));
+                    }
+                }
+                entries
+            }
+            _ => entries_from(&autnum_prefixes, &mut warnings),
+        };
+        for warning in warnings {
+            eprintln!("Warning: {}: {}", filter_name, warning);
+        }
+        let mut members: Map<Vec<String>, Vec<AggPrefix>> = Map::new();
+        for (path, entry) in set_entries {
+            if let Some(bogon) = bogons.find(&(entry.prefix, entry.mask)) {
+                eprintln!(
+                    "Warning: {}: dropped bogon {} from {} (matches {})",
+                    filter_name,
+                    CiscoEntryFmt(&entry),
+                    path.join(" > "),
+                    CiscoEntryFmt(bogon)
+                );
+            } else {
+                members.entry(path).or_default().push(entry);
+            }
+        }
+        // prefixes per IRR source, with `provenance`
+        let mut per_source: Map<&str, Set<Prefix>> = Map::new();
+        for entry in members.values().flatten() {
+            let prefix = (entry.prefix, entry.mask);
+            for source in route_sources.get(&prefix).into_iter().flatten() {
+                per_source.entry(source).or_default().insert(prefix);
+            }
+        }
+        // the chain of sets down to AS number `num`
+        let chain = |num: u32| {
+            let mut chain = as_set_paths
+                .get(filter_name)
+                .and_then(|paths| paths.get(&num))
+                .cloned()
+                .unwrap_or_else(|| vec![filter_name.to_string()]);
+            chain.push(format!("AS{}", num));
+            chain.dedup();
+            chain
+        };
+
+        let mut validity_counts: Map<Validity, usize> = Map::new();
+        for num in origins.iter() {
+            let mut entries = Vec::new();
+            for prefix in autnum_prefixes[num].iter() {
+                if let Some(bogon) = bogons.find(prefix) {
+                    eprintln!(
+                        "Warning: {}: dropped bogon route {}/{} origin AS{} (matches {})",
+                        filter_name,
+                        prefix.0,
+                        prefix.1,
+                        num,
+                        CiscoEntryFmt(bogon)
+                    );
+                    continue;
+                }
+                if let (Some(vrps), false) = (vrps, rpki_policy == RpkiPolicy::Ignore) {
+                    let validity = vrps.validate(prefix, *num);
+                    *validity_counts.entry(validity).or_default() += 1;
+                    if validity == Validity::Invalid {
+                        if rpki_policy == RpkiPolicy::Drop {
+                            continue;
+                        }
+                        notes.push(format!(
+                            "RPKI invalid: {}/{} origin AS{}",
+                            prefix.0, prefix.1, num
+                        ));
+                    }
+                }
+                if prefix_set.insert(*prefix) {
+                    entries.push(AggPrefix::from_prefix(prefix));
+                }
+            }
+            members.insert(chain(*num), entries);
+        }
+        if !validity_counts.is_empty() {
+            let count = |validity| validity_counts.get(&validity).copied().unwrap_or(0);
+            eprintln!(
+                "RPKI for {}: {} valid, {} invalid, {} not-found",
+                filter_name,
+                count(Validity::Valid),
+                count(Validity::Invalid),
+                count(Validity::NotFound)
+            );
+        }
+        notes.sort_unstable();
+        notes.dedup();
+
+        if let Some(autnum_routes) = autnum_routes.as_ref() {
+            for route in origins.iter().flat_map(|num| autnum_routes[num].iter()) {
+                per_source
+                    .entry(route.source.as_str())
+                    .or_default()
+                    .insert(route.prefix);
+            }
+        }
+        if !per_source.is_empty() {
+            let mut per_source: Vec<String> = per_source
+                .into_iter()
+                .map(|(source, prefixes)| format!("{} {}", source, prefixes.len()))
+                .collect();
+            per_source.sort_unstable();
+            eprintln!("Sources for {}: {}", filter_name, per_source.join(", "));
+        }
+
+        // ROA-derived entries for the filter's origins, permitting up to maxLength
+        match (vrps, root_config.rpki.as_ref()) {
+            (Some(vrps), Some(rpki)) if rpki.augment.unwrap_or(false) => {
+                for num in origins.iter() {
+                    let entries: Vec<AggPrefix> = vrps
+                        .for_asn(*num)
+                        .iter()
+                        .filter(|vrp| bogons.find(&vrp.prefix).is_none())
+                        .map(Vrp::to_entry)
+                        .collect();
+                    if !entries.is_empty() {
+                        let mut chain = chain(*num);
+                        chain.push("ROA".to_string());
+                        members.insert(chain, entries);
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        let mut members: Vec<Member> = members.into_iter().collect();
+        members.sort_unstable();
+        results.insert(filter_name, Resolved { members, notes });
+    });
+    Ok(results)
+}
+
+/// Entries of `filter_name` that come from route-sets, filter-sets,
+/// policies or named filters, each with the objects it comes from; none for
+/// filters of origins. Problems go to `warnings`.
+fn set_entries(
+    filter_name: &str,
+    named_filters: &Map<&str, Filter>,
+    policies: &Map<(u32, u32), Result<Vec<Export>, String>>,
+    resolver: &FilterSets,
+    warnings: &mut Vec<String>,
+) -> Vec<(Vec<String>, AggPrefix)> {
+    let mut set_entries = Vec::new();
+    if let Some(filter) = named_filters.get(filter_name) {
+        match resolver.eval_filter(filter) {
+            Ok(entries) => {
+                set_entries = entries
+                    .into_iter()
+                    .map(|entry| (vec![filter_name.to_string()], entry))
+                    .collect()
+            }
+            Err(e) => warnings.push(e.to_string()),
+        }
+        return set_entries;
+    }
+    match FilterClass::try_from(filter_name).expect("BUG: invalid filter") {
+        FilterClass::RouteSet(name) => set_entries = resolver.route_sets.flatten(name),
+        FilterClass::FilterSet(name) => match resolver.eval(name) {
+            Ok(entries) => {
+                set_entries = entries
+                    .into_iter()
+                    .map(|entry| (vec![name.to_string()], entry))
+                    .collect()
+            }
+            Err(e) => warnings.push(e.to_string()),
+        },
+        FilterClass::Policy(from, to) => match &policies[&(from, to)] {
+            Ok(exports) if exports.is_empty() => {
+                warnings.push(format!("AS{} exports nothing to AS{}", from, to))
+            }
+            Ok(exports) => {
+                for export in exports {
+                    let path = vec![format!("AS{} announce {}", from, export.filter)];
+                    match resolver.eval_filter(&export.filter) {
+                        Ok(entries) => set_entries.extend(
+                            entries
+                                .into_iter()
+                                .filter(|entry| export.covers_family(&entry.prefix))
+                                .map(|entry| (path.clone(), entry)),
+                        ),
+                        Err(e) => warnings.push(e.to_string()),
+                    }
+                }
+            }
+            Err(e) => warnings.push(e.clone()),
+        },
+        FilterClass::AsSet(_) | FilterClass::AutNum(_) => (),
+    }
+    set_entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix_list(name: &str, comment: &str, entries: &[&str]) -> String {
+        let entries: Vec<AggPrefix> = entries.iter().map(|entry| entry.parse().unwrap()).collect();
+        CiscoPrefixList((name, name), comment, &entries, &[]).to_string()
+    }
+
+    #[test]
+    fn ignores_timestamps() {
+        let comment = comment();
+        let earlier = format!("{} at 2021-03-01T12:00:00+0100", comment);
+        let later = format!("{} at 2021-03-02T12:00:00+0100", comment);
+        let entries = ["192.0.2.0/24"];
+        assert_eq!(
+            without_timestamps(&prefix_list("AS-FOO", &earlier, &entries)),
+            without_timestamps(&prefix_list("AS-FOO", &later, &entries))
+        );
+        assert_eq!(
+            without_timestamps(&prefix_list("AS-FOO", &earlier, &entries)),
+            without_timestamps(&prefix_list("AS-FOO", &comment, &entries))
+        );
+        assert_ne!(
+            without_timestamps(&prefix_list("AS-FOO", &earlier, &entries)),
+            without_timestamps(&prefix_list("AS-FOO", &later, &["192.0.2.0/23"]))
+        );
+    }
+
+    #[test]
+    fn finds_changed_filters() {
+        let comment = comment();
+        let previous = prefix_list(
+            "AS-FOO",
+            &format!("{} at 2021-03-01T12:00:00+0100", comment),
+            &["192.0.2.0/24"],
+        ) + &prefix_list("AS-GONE", &comment, &["198.51.100.0/24"])
+            + &prefix_list("AS-BAR", &comment, &["2001:db8::/32"]);
+        let entries = |entries: &[&str]| -> Vec<AggPrefix> {
+            entries.iter().map(|entry| entry.parse().unwrap()).collect()
+        };
+        let filters = vec![
+            (("ip", "AS-FOO"), entries(&["192.0.2.0/24"])),
+            (("ipv6", "AS-FOO"), entries(&[])),
+            (("ip", "AS-BAR"), entries(&[])),
+            (
+                ("ipv6", "AS-BAR"),
+                entries(&["2001:db8::/32", "2001:db8:1::/48"]),
+            ),
+            (("ip", "AS-NEW"), entries(&["203.0.113.0/24"])),
+            (("ipv6", "AS-NEW"), entries(&[])),
+        ];
+        assert_eq!(
+            changed_filters(&previous, filters.into_iter()),
+            vec!["AS-BAR", "AS-GONE", "AS-NEW"]
+        );
+        let unchanged = vec![
+            (("ip", "AS-FOO"), entries(&["192.0.2.0/24"])),
+            (("ipv6", "AS-FOO"), entries(&[])),
+        ];
+        let previous = prefix_list("AS-FOO", &comment, &["192.0.2.0/24"]);
+        assert!(changed_filters(&previous, unchanged.into_iter()).is_empty());
+    }
+}
@@ -0,0 +1,524 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::{read_to_string, rename, File},
+    io::{self, BufWriter, Write},
+};
+
+use fup::{aggregate::AggPrefix, AppResult, Map, Set};
+use serde_derive::{Deserialize, Serialize};
+
+/// What a run generated for each router, kept in the output directory so the
+/// next run can report what changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// when the snapshot was taken
+    pub time: String,
+    pub routers: BTreeMap<String, Vec<FilterSnapshot>>,
+}
+
+/// A filter of a router as generated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterSnapshot {
+    pub object: String,
+    /// IPv4 and IPv6 output names
+    pub names: (String, String),
+    /// entries before aggregation, by the chain of objects from the filter
+    /// down to the member they come from
+    pub members: Vec<(Vec<String>, Vec<String>)>,
+    /// entries as written
+    pub entries: Vec<String>,
+}
+
+/// Where the snapshot of the last run is kept.
+pub fn path(outputdir: &str) -> String {
+    format!("{}/snapshot.json", outputdir)
+}
+
+impl Snapshot {
+    /// The snapshot at `path`, or `None` if there is none yet.
+    pub fn load(path: &str) -> AppResult<Option<Snapshot>> {
+        match read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(|e| format!("failed to parse {}: {}", path, e).into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("failed to read {}: {}", path, e).into()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> AppResult<()> {
+        let temp_path = format!("{}.tmp", path);
+        let file = File::create(&temp_path)
+            .map_err(|e| format!("failed to create {}: {}", temp_path, e))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .map_err(|e| format!("failed to write {}: {}", temp_path, e))?;
+        writer
+            .flush()
+            .map_err(|e| format!("failed to write {}: {}", temp_path, e))?;
+        rename(&temp_path, path).map_err(|e| format!("rename {} to {}: {}", temp_path, path, e))?;
+        Ok(())
+    }
+}
+
+/// Report formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Markdown,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "markdown" => Ok(Format::Markdown),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown report format {}", s)),
+        }
+    }
+}
+
+/// Changes of the routers' filters since a snapshot.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// when the snapshot compared with was taken
+    pub since: Option<String>,
+    pub routers: Vec<RouterReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouterReport {
+    pub hostname: String,
+    pub filters: Vec<FilterReport>,
+}
+
+/// Changes of one filter. Prefixes come with the member they are from.
+#[derive(Debug, Default, Serialize)]
+pub struct FilterReport {
+    pub name: String,
+    pub object: String,
+    /// "added", "removed" or "changed"
+    pub status: &'static str,
+    pub added_members: Vec<Vec<String>>,
+    pub removed_members: Vec<Vec<String>>,
+    pub added_prefixes: Vec<(String, String)>,
+    pub removed_prefixes: Vec<(String, String)>,
+    pub added_entries: Vec<String>,
+    pub removed_entries: Vec<String>,
+}
+
+impl FilterSnapshot {
+    /// The name the filter is known by on its router.
+    fn name(&self) -> String {
+        match self.names.0 == self.names.1 {
+            true => self.names.0.clone(),
+            false => format!("{}/{}", self.names.0, self.names.1),
+        }
+    }
+
+    /// Each entry before aggregation with the member it comes from.
+    fn prefixes(&self) -> Map<AggPrefix, &str> {
+        let mut prefixes = Map::new();
+        for (chain, entries) in self.members.iter() {
+            let member = chain.last().map(String::as_str).unwrap_or_default();
+            for entry in entries.iter().filter_map(|entry| entry.parse().ok()) {
+                prefixes.entry(entry).or_insert(member);
+            }
+        }
+        prefixes
+    }
+}
+
+impl Report {
+    /// Compares the filters of each router, as `(hostname, filters)`, with
+    /// those in `snapshot`.
+    pub fn new(snapshot: Option<&Snapshot>, routers: &[(String, Vec<FilterSnapshot>)]) -> Report {
+        let mut report = Report {
+            since: snapshot.map(|snapshot| snapshot.time.clone()),
+            routers: Vec::new(),
+        };
+        let empty = BTreeMap::new();
+        let before = snapshot.map(|snapshot| &snapshot.routers).unwrap_or(&empty);
+        for (hostname, filters) in routers {
+            let mut old: Vec<&FilterSnapshot> = before
+                .get(hostname)
+                .map(|filters| filters.iter().collect())
+                .unwrap_or_default();
+            let mut reports = Vec::new();
+            for filter in filters {
+                let previous = old
+                    .iter()
+                    .position(|old| old.names == filter.names)
+                    .map(|i| old.remove(i));
+                reports.extend(compare(previous, Some(filter)));
+            }
+            reports.extend(old.into_iter().filter_map(|old| compare(Some(old), None)));
+            if !reports.is_empty() {
+                report.routers.push(RouterReport {
+                    hostname: hostname.clone(),
+                    filters: reports,
+                });
+            }
+        }
+        report
+    }
+
+    pub fn format(&self, format: Format) -> AppResult<String> {
+        if format == Format::Json {
+            return Ok(serde_json::to_string_pretty(self)?);
+        }
+        let markdown = format == Format::Markdown;
+        let mut out = String::new();
+        match (&self.since, markdown) {
+            (Some(since), true) => writeln!(out, "# Changes since {}\n", since)?,
+            (Some(since), false) => writeln!(out, "Changes since {}", since)?,
+            (None, true) => writeln!(out, "# Changes\n\nNo previous snapshot.\n")?,
+            (None, false) => writeln!(out, "No previous snapshot.")?,
+        }
+        if self.routers.is_empty() {
+            writeln!(out, "No changes.")?;
+        }
+        for router in self.routers.iter() {
+            match markdown {
+                true => writeln!(out, "## {}\n", router.hostname)?,
+                false => writeln!(out, "\n{}", router.hostname)?,
+            }
+            for filter in router.filters.iter() {
+                let title = match filter.name == filter.object {
+                    true => format!("{} ({})", filter.name, filter.status),
+                    false => format!("{} ({}, {})", filter.name, filter.object, filter.status),
+                };
+                let mut lines = Vec::new();
+                for (chain, verb) in filter
+                    .added_members
+                    .iter()
+                    .map(|chain| (chain, "added"))
+                    .chain(
+                        filter
+                            .removed_members
+                            .iter()
+                            .map(|chain| (chain, "removed")),
+                    )
+                {
+                    lines.push(describe(chain, verb));
+                }
+                for (list, verb) in [
+                    (&filter.added_prefixes, "added"),
+                    (&filter.removed_prefixes, "removed"),
+                ] {
+                    lines.extend(list.iter().map(|(prefix, member)| match markdown {
+                        true => format!("prefix `{}` {} ({})", prefix, verb, member),
+                        false => format!("prefix {} {} ({})", prefix, verb, member),
+                    }));
+                }
+                for (list, verb) in [
+                    (&filter.added_entries, "added"),
+                    (&filter.removed_entries, "removed"),
+                ] {
+                    lines.extend(list.iter().map(|entry| match markdown {
+                        true => format!("entry `{}` {}", entry, verb),
+                        false => format!("entry {} {}", entry, verb),
+                    }));
+                }
+                match markdown {
+                    true => writeln!(out, "### {}\n", title)?,
+                    false => writeln!(out, "  {}", title)?,
+                }
+                for line in lines {
+                    match markdown {
+                        true => writeln!(out, "- {}", line)?,
+                        false => writeln!(out, "    {}", line)?,
+                    }
+                }
+                if markdown {
+                    writeln!(out)?;
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// What changed between two versions of a filter, if anything.
+fn compare(old: Option<&FilterSnapshot>, new: Option<&FilterSnapshot>) -> Option<FilterReport> {
+    let filter = new.or(old)?;
+    let mut report = FilterReport {
+        name: filter.name(),
+        object: filter.object.clone(),
+        status: match (old, new) {
+            (None, _) => "added",
+            (_, None) => "removed",
+            _ => "changed",
+        },
+        ..Default::default()
+    };
+
+    let chains = |filter: Option<&FilterSnapshot>| -> Set<Vec<String>> {
+        filter
+            .into_iter()
+            .flat_map(|filter| filter.members.iter().map(|(chain, _)| chain.clone()))
+            .collect()
+    };
+    let (old_chains, new_chains) = (chains(old), chains(new));
+    report.added_members = new_chains.difference(&old_chains).cloned().collect();
+    report.removed_members = old_chains.difference(&new_chains).cloned().collect();
+    report.added_members.sort_unstable();
+    report.removed_members.sort_unstable();
+
+    let old_prefixes = old.map(FilterSnapshot::prefixes).unwrap_or_default();
+    let new_prefixes = new.map(FilterSnapshot::prefixes).unwrap_or_default();
+    let difference = |a: &Map<AggPrefix, &str>, b: &Map<AggPrefix, &str>| {
+        let mut prefixes: Vec<(&AggPrefix, &str)> = a
+            .iter()
+            .filter(|(prefix, _)| !b.contains_key(prefix))
+            .map(|(prefix, member)| (prefix, *member))
+            .collect();
+        prefixes.sort_unstable();
+        prefixes
+            .into_iter()
+            .map(|(prefix, member)| (entry_string(prefix), member.to_string()))
+            .collect()
+    };
+    report.added_prefixes = difference(&new_prefixes, &old_prefixes);
+    report.removed_prefixes = difference(&old_prefixes, &new_prefixes);
+
+    let entries = |filter: Option<&FilterSnapshot>| -> Set<AggPrefix> {
+        filter
+            .into_iter()
+            .flat_map(|filter| filter.entries.iter())
+            .filter_map(|entry| entry.parse().ok())
+            .collect()
+    };
+    let (old_entries, new_entries) = (entries(old), entries(new));
+    let difference = |a: &Set<AggPrefix>, b: &Set<AggPrefix>| {
+        let mut entries: Vec<&AggPrefix> = a.difference(b).collect();
+        entries.sort_unstable();
+        entries.into_iter().map(entry_string).collect()
+    };
+    report.added_entries = difference(&new_entries, &old_entries);
+    report.removed_entries = difference(&old_entries, &new_entries);
+
+    let unchanged = report.added_members.is_empty()
+        && report.removed_members.is_empty()
+        && report.added_prefixes.is_empty()
+        && report.removed_prefixes.is_empty()
+        && report.added_entries.is_empty()
+        && report.removed_entries.is_empty();
+    match unchanged && report.status == "changed" {
+        true => None,
+        false => Some(report),
+    }
+}
+
+/// A member that came or went, e.g. "AS64500 added to AS-ACME via
+/// AS-ACME-DOWNSTREAM".
+fn describe(chain: &[String], verb: &str) -> String {
+    let preposition = if verb == "added" { "to" } else { "from" };
+    match chain {
+        [] => String::new(),
+        [member] => format!("{} {}", member, verb),
+        [filter, member] => format!("{} {} {} {}", member, verb, preposition, filter),
+        [filter, via @ .., member] => format!(
+            "{} {} {} {} via {}",
+            member,
+            verb,
+            preposition,
+            filter,
+            via.join(" > ")
+        ),
+    }
+}
+
+pub fn entry_string(entry: &AggPrefix) -> String {
+    fup::format::CiscoEntryFmt(entry).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(object: &str, members: &[(&[&str], &[&str])], entries: &[&str]) -> FilterSnapshot {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        FilterSnapshot {
+            object: object.to_string(),
+            names: (object.to_string(), object.to_string()),
+            members: members
+                .iter()
+                .map(|(chain, entries)| (strings(chain), strings(entries)))
+                .collect(),
+            entries: strings(entries),
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        let filters = vec![
+            filter(
+                "AS-ACME",
+                &[(&["AS-ACME", "AS64501"], &["198.51.100.0/24"])],
+                &["198.51.100.0/24"],
+            ),
+            filter(
+                "AS-OLD",
+                &[(&["AS-OLD", "AS64502"], &["203.0.113.0/24"])],
+                &["203.0.113.0/24"],
+            ),
+            filter(
+                "AS64503",
+                &[(&["AS64503"], &["192.0.2.0/24"])],
+                &["192.0.2.0/24"],
+            ),
+        ];
+        Snapshot {
+            time: "2021-03-01T12:00:00+0100".to_string(),
+            routers: vec![("r1".to_string(), filters)].into_iter().collect(),
+        }
+    }
+
+    fn routers() -> Vec<(String, Vec<FilterSnapshot>)> {
+        let filters = vec![
+            filter(
+                "AS-ACME",
+                &[
+                    (&["AS-ACME", "AS64501"], &["198.51.100.0/24"]),
+                    (
+                        &["AS-ACME", "AS-ACME-DOWNSTREAM", "AS64500"],
+                        &["198.51.101.0/24"],
+                    ),
+                ],
+                &["198.51.100.0/23"],
+            ),
+            filter(
+                "AS64503",
+                &[(&["AS64503"], &["192.0.2.0/24"])],
+                &["192.0.2.0/24"],
+            ),
+            filter(
+                "AS-NEW",
+                &[(&["AS-NEW", "AS64504"], &["10.0.0.0/8"])],
+                &["10.0.0.0/8"],
+            ),
+        ];
+        vec![("r1".to_string(), filters)]
+    }
+
+    #[test]
+    fn compares_filters() {
+        let report = Report::new(Some(&snapshot()), &routers());
+        assert_eq!(report.since.as_deref(), Some("2021-03-01T12:00:00+0100"));
+        assert_eq!(report.routers.len(), 1);
+        let filters = &report.routers[0].filters;
+        let statuses: Vec<(&str, &str)> = filters
+            .iter()
+            .map(|filter| (filter.name.as_str(), filter.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("AS-ACME", "changed"),
+                ("AS-NEW", "added"),
+                ("AS-OLD", "removed")
+            ]
+        );
+
+        let acme = &filters[0];
+        assert_eq!(
+            acme.added_members,
+            vec![vec!["AS-ACME", "AS-ACME-DOWNSTREAM", "AS64500"]]
+        );
+        assert!(acme.removed_members.is_empty());
+        assert_eq!(
+            acme.added_prefixes,
+            vec![("198.51.101.0/24".to_string(), "AS64500".to_string())]
+        );
+        assert_eq!(acme.added_entries, vec!["198.51.100.0/23"]);
+        assert_eq!(acme.removed_entries, vec!["198.51.100.0/24"]);
+
+        let old = &filters[2];
+        assert_eq!(old.removed_members, vec![vec!["AS-OLD", "AS64502"]]);
+        assert_eq!(
+            old.removed_prefixes,
+            vec![("203.0.113.0/24".to_string(), "AS64502".to_string())]
+        );
+
+        // every filter is new without a snapshot
+        let report = Report::new(None, &routers());
+        assert!(report.routers[0]
+            .filters
+            .iter()
+            .all(|filter| filter.status == "added"));
+        // and nothing changed against itself
+        let snapshot = snapshot();
+        let same: Vec<(String, Vec<FilterSnapshot>)> = snapshot
+            .routers
+            .iter()
+            .map(|(hostname, filters)| (hostname.clone(), filters.clone()))
+            .collect();
+        assert!(Report::new(Some(&snapshot), &same).routers.is_empty());
+    }
+
+    #[test]
+    fn describes_members() {
+        let chain =
+            |chain: &[&str]| -> Vec<String> { chain.iter().map(|s| s.to_string()).collect() };
+        assert_eq!(
+            describe(
+                &chain(&["AS-ACME", "AS-ACME-DOWNSTREAM", "AS64500"]),
+                "added"
+            ),
+            "AS64500 added to AS-ACME via AS-ACME-DOWNSTREAM"
+        );
+        assert_eq!(
+            describe(&chain(&["AS-ACME", "AS-A", "AS-B", "AS64500"]), "removed"),
+            "AS64500 removed from AS-ACME via AS-A > AS-B"
+        );
+        assert_eq!(
+            describe(&chain(&["AS-ACME", "AS64501"]), "removed"),
+            "AS64501 removed from AS-ACME"
+        );
+        assert_eq!(describe(&chain(&["AS64503"]), "added"), "AS64503 added");
+    }
+
+    #[test]
+    fn formats_reports() {
+        let report = Report::new(Some(&snapshot()), &routers());
+        assert_eq!(
+            report.format(Format::Text).unwrap(),
+            "Changes since 2021-03-01T12:00:00+0100\n\
+             \n\
+             r1\n\
+             \x20 AS-ACME (changed)\n\
+             \x20   AS64500 added to AS-ACME via AS-ACME-DOWNSTREAM\n\
+             \x20   prefix 198.51.101.0/24 added (AS64500)\n\
+             \x20   entry 198.51.100.0/23 added\n\
+             \x20   entry 198.51.100.0/24 removed\n\
+             \x20 AS-NEW (added)\n\
+             \x20   AS64504 added to AS-NEW\n\
+             \x20   prefix 10.0.0.0/8 added (AS64504)\n\
+             \x20   entry 10.0.0.0/8 added\n\
+             \x20 AS-OLD (removed)\n\
+             \x20   AS64502 removed from AS-OLD\n\
+             \x20   prefix 203.0.113.0/24 removed (AS64502)\n\
+             \x20   entry 203.0.113.0/24 removed\n"
+        );
+        let markdown = report.format(Format::Markdown).unwrap();
+        assert!(markdown.starts_with("# Changes since 2021-03-01T12:00:00+0100\n\n## r1\n\n"));
+        assert!(markdown.contains(
+            "### AS-ACME (changed)\n\n\
+             - AS64500 added to AS-ACME via AS-ACME-DOWNSTREAM\n\
+             - prefix `198.51.101.0/24` added (AS64500)\n"
+        ));
+        let json: serde_json::Value =
+            serde_json::from_str(&report.format(Format::Json).unwrap()).unwrap();
+        assert_eq!(json["routers"][0]["filters"][2]["status"], "removed");
+
+        assert_eq!(
+            Report::default().format(Format::Text).unwrap(),
+            "No previous snapshot.\nNo changes.\n"
+        );
+    }
+}
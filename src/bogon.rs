@@ -0,0 +1,105 @@
+//! Bogon and martian prefixes, which never belong in a customer filter.
+
+use crate::{aggregate::AggPrefix, AppResult, Prefix};
+
+/// Built-in IPv4 bogons, in prefix-list notation.
+pub const BOGONS_V4: &[&str] = &[
+    "0.0.0.0/0",             // default route
+    "0.0.0.0/8 le 32",       // "this network", RFC 1122
+    "10.0.0.0/8 le 32",      // private, RFC 1918
+    "100.64.0.0/10 le 32",   // shared address space, RFC 6598
+    "127.0.0.0/8 le 32",     // loopback, RFC 1122
+    "169.254.0.0/16 le 32",  // link local, RFC 3927
+    "172.16.0.0/12 le 32",   // private, RFC 1918
+    "192.0.0.0/24 le 32",    // IETF protocol assignments, RFC 6890
+    "192.0.2.0/24 le 32",    // TEST-NET-1, RFC 5737
+    "192.168.0.0/16 le 32",  // private, RFC 1918
+    "198.18.0.0/15 le 32",   // benchmarking, RFC 2544
+    "198.51.100.0/24 le 32", // TEST-NET-2, RFC 5737
+    "203.0.113.0/24 le 32",  // TEST-NET-3, RFC 5737
+    "224.0.0.0/4 le 32",     // multicast, RFC 5771
+    "240.0.0.0/4 le 32",     // reserved, RFC 1112
+];
+
+/// Built-in IPv6 bogons, in prefix-list notation.
+pub const BOGONS_V6: &[&str] = &[
+    "::/0",                 // default route
+    "::/8 le 128",          // loopback, unspecified, IPv4-mapped, RFC 4291
+    "100::/64 le 128",      // discard only, RFC 6666
+    "2001:2::/48 le 128",   // benchmarking, RFC 5180
+    "2001:10::/28 le 128",  // ORCHID, RFC 4843
+    "2001:db8::/32 le 128", // documentation, RFC 3849
+    "3ffe::/16 le 128",     // old 6bone, RFC 3701
+    "fc00::/7 le 128",      // unique local, RFC 4193
+    "fe80::/10 le 128",     // link local, RFC 4291
+    "fec0::/10 le 128",     // old site local, RFC 3879
+    "ff00::/8 le 128",      // multicast, RFC 4291
+];
+
+#[derive(Debug, Clone)]
+pub struct Bogons {
+    entries: Vec<AggPrefix>,
+}
+
+impl Bogons {
+    /// Parses a list of entries such as `10.0.0.0/8 le 32`.
+    pub fn new<S: AsRef<str>>(entries: &[S]) -> AppResult<Self> {
+        let entries = entries
+            .iter()
+            .map(|s| {
+                s.as_ref()
+                    .parse()
+                    .map_err(|e| format!("invalid bogon {}: {}", s.as_ref(), e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Bogons { entries })
+    }
+
+    /// The built-in lists, with either family optionally replaced.
+    pub fn with_overrides(v4: Option<&[String]>, v6: Option<&[String]>) -> AppResult<Self> {
+        let mut entries = match v4 {
+            Some(v4) => Bogons::new(v4)?.entries,
+            None => Bogons::new(BOGONS_V4)?.entries,
+        };
+        entries.extend(match v6 {
+            Some(v6) => Bogons::new(v6)?.entries,
+            None => Bogons::new(BOGONS_V6)?.entries,
+        });
+        Ok(Bogons { entries })
+    }
+
+    pub fn none() -> Self {
+        Bogons { entries: vec![] }
+    }
+
+    /// The bogon entry matching `prefix`, if any.
+    pub fn find(&self, prefix: &Prefix) -> Option<&AggPrefix> {
+        self.entries.iter().find(|entry| entry.matches(prefix))
+    }
+}
+
+impl Default for Bogons {
+    fn default() -> Self {
+        Bogons::with_overrides(None, None).expect("BUG: invalid built-in bogon")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irr::parse_prefix;
+
+    #[test]
+    fn finds_bogons() {
+        let bogons = Bogons::default();
+        let bogon = |s| bogons.find(&parse_prefix(s).unwrap()).is_some();
+        assert!(bogon("0.0.0.0/0"));
+        assert!(bogon("::/0"));
+        assert!(bogon("10.1.0.0/16"));
+        assert!(bogon("2001:db8:1::/48"));
+        assert!(!bogon("193.0.0.0/21"));
+        assert!(!bogon("2001:67c:2e8::/48"));
+        // only the default route itself, not everything below it
+        assert!(!bogon("8.0.0.0/8"));
+    }
+}
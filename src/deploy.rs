@@ -0,0 +1,607 @@
+//! Applying generated filters to routers: NETCONF `edit-config` on the
+//! candidate datastore and a confirmed commit on IOS XR and Junos, the CLI
+//! with a revert timer on IOS. Changes that fail are rolled back.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    aggregate::AggPrefix,
+    format::CiscoEntryFmt,
+    netconf::{self, Session},
+    AppResult,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// IOS XR over NETCONF, filters as prefix-sets
+    Xr,
+    /// Junos over NETCONF, filters as route-filter-lists
+    Junos,
+    /// IOS over the CLI, the generated configuration as is
+    Ios,
+}
+
+impl FromStr for Platform {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xr" => Ok(Platform::Xr),
+            "junos" => Ok(Platform::Junos),
+            "ios" => Ok(Platform::Ios),
+            _ => Err(format!("unknown platform {}", s).into()),
+        }
+    }
+}
+
+impl Platform {
+    /// The ssh subsystem the platform is configured through, none for a
+    /// shell.
+    pub fn subsystem(self) -> Option<&'static str> {
+        match self {
+            Platform::Xr | Platform::Junos => Some("netconf"),
+            Platform::Ios => None,
+        }
+    }
+}
+
+/// A connection to a router, usually an ssh process.
+pub struct Channel {
+    reader: Box<dyn Read + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Child>,
+}
+
+impl Channel {
+    pub fn new(reader: impl Read + Send + 'static, writer: impl Write + Send + 'static) -> Self {
+        Channel {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            child: None,
+        }
+    }
+
+    /// Runs `ssh`, a command and its arguments, to `host`, with `subsystem`
+    /// or else a shell at the other end.
+    pub fn ssh(
+        ssh: &[String],
+        host: &str,
+        port: Option<u16>,
+        subsystem: Option<&str>,
+    ) -> AppResult<Self> {
+        let (program, args) = ssh.split_first().ok_or("empty ssh command")?;
+        let mut command = Command::new(program);
+        command.args(args);
+        if let Some(port) = port {
+            command.arg("-p").arg(port.to_string());
+        }
+        match subsystem {
+            Some(subsystem) => command.arg("-s").arg(host).arg(subsystem),
+            None => command.arg("-T").arg(host),
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run {}: {}", program, e))?;
+        let (stdin, stdout) = (child.stdin.take(), child.stdout.take());
+        let mut channel =
+            Channel::new(stdout.ok_or("no ssh output")?, stdin.ok_or("no ssh input")?);
+        channel.child = Some(child);
+        Ok(channel)
+    }
+
+    /// Waits for the ssh process, if any, once the connection is done with
+    /// and `result` known. The process is killed if the connection failed,
+    /// as a router that stopped answering would keep it running.
+    fn finish<T>(child: Option<Child>, result: AppResult<T>) -> AppResult<T> {
+        if let Some(mut child) = child {
+            if result.is_err() {
+                child.kill().ok();
+                child.wait().ok();
+                return result;
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(format!("ssh failed with {}", status).into());
+            }
+        }
+        result
+    }
+
+    /// Sends `input`, closes the connection's input and returns everything
+    /// the router printed, giving up after `timeout` seconds of silence.
+    fn run(self, input: &str, timeout: u32) -> AppResult<String> {
+        let Channel {
+            reader,
+            mut writer,
+            child,
+        } = self;
+        let output = (move || {
+            writer.write_all(input.as_bytes())?;
+            writer.flush()?;
+            drop(writer);
+            let mut output = String::new();
+            Deadline::new(reader, timeout).read_to_string(&mut output)?;
+            Ok(output)
+        })();
+        Channel::finish(child, output)
+    }
+}
+
+/// Reads through another thread, failing with `TimedOut` once the reader
+/// has been silent for `timeout` seconds, and for good after that.
+struct Deadline {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    pending: Vec<u8>,
+    timeout: u32,
+    expired: bool,
+}
+
+impl Deadline {
+    fn new(mut reader: Box<dyn Read + Send>, timeout: u32) -> Self {
+        let (sender, chunks) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0; 8192];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => Ok(buf[..len].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                if sender.send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Deadline {
+            chunks,
+            pending: Vec::new(),
+            timeout,
+            expired: false,
+        }
+    }
+
+    fn timed_out(&self) -> io::Error {
+        let message = format!("no answer in {} s", self.timeout);
+        io::Error::new(io::ErrorKind::TimedOut, message)
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            if self.expired {
+                return Err(self.timed_out());
+            }
+            let timeout = Duration::from_secs(self.timeout.into());
+            match self.chunks.recv_timeout(timeout) {
+                Ok(chunk) => self.pending = chunk?,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.expired = true;
+                    return Err(self.timed_out());
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+/// What to apply to a router.
+pub struct Change<'a> {
+    /// the generated configuration, which the CLI takes as is
+    pub config: &'a str,
+    /// entries of each prefix-list or prefix-set by name, for NETCONF
+    pub filters: &'a [(&'a str, Vec<AggPrefix>)],
+}
+
+/// Applies `change` to a router on `platform`, through connections from
+/// `open` to the ssh subsystem it is given, if any. Unconfirmed changes are
+/// rolled back after `timeout` seconds.
+pub fn apply(
+    platform: Platform,
+    mut open: impl FnMut(Option<&str>) -> AppResult<Channel>,
+    change: &Change,
+    timeout: u32,
+) -> AppResult<()> {
+    match platform {
+        Platform::Xr => apply_netconf(open(platform.subsystem())?, &xr_config(change), timeout),
+        Platform::Junos => {
+            apply_netconf(open(platform.subsystem())?, &junos_config(change), timeout)
+        }
+        Platform::Ios => apply_cli(|| open(None), change.config, timeout),
+    }
+}
+
+/// Edits the candidate configuration and commits it, confirmed. The session
+/// is closed and the ssh process waited for whatever happens.
+fn apply_netconf(channel: Channel, config: &str, timeout: u32) -> AppResult<()> {
+    let Channel {
+        reader,
+        writer,
+        child,
+    } = channel;
+    let reader = BufReader::new(Deadline::new(reader, timeout));
+    let result = Session::new(reader, writer).and_then(|mut session| {
+        let result = edit_candidate(&mut session, config, timeout);
+        let closed = session.close();
+        result.and(closed)
+    });
+    Channel::finish(child, result)
+}
+
+fn edit_candidate<R: BufRead, W: Write>(
+    session: &mut Session<R, W>,
+    config: &str,
+    timeout: u32,
+) -> AppResult<()> {
+    if !session.has_capability(netconf::CANDIDATE) {
+        return Err("the router has no candidate configuration".into());
+    }
+    if !session.has_capability(netconf::CONFIRMED_COMMIT) {
+        return Err("the router has no confirmed commits".into());
+    }
+    session.lock("candidate")?;
+    let result = match session
+        .edit_config("candidate", config)
+        .and_then(|()| session.commit_confirmed(timeout))
+    {
+        Ok(()) => session.commit().map_err(|e| {
+            let rollback = match session.has_capability(netconf::CONFIRMED_COMMIT_11) {
+                true => session.cancel_commit().map(|()| "rolled back".to_string()),
+                false => Err("no cancel-commit".into()),
+            };
+            rollback_error(&e.to_string(), rollback, timeout)
+        }),
+        Err(e) => Err(match session.discard_changes() {
+            Ok(()) => format!("{}, changes discarded", e),
+            Err(discard) => format!("{}, failed to discard changes: {}", e, discard),
+        }),
+    };
+    // the server releases the lock with the session anyway
+    let unlocked = session.unlock("candidate");
+    result?;
+    unlocked
+}
+
+/// Enters `config` with a revert timer, then confirms it, or reverts it at
+/// once if IOS reported errors.
+fn apply_cli(
+    mut open: impl FnMut() -> AppResult<Channel>,
+    config: &str,
+    timeout: u32,
+) -> AppResult<()> {
+    let minutes = timeout.div_ceil(60).clamp(1, 120);
+    let mut input = format!(
+        "terminal length 0\nconfigure terminal revert timer {}\n",
+        minutes
+    );
+    for line in config.lines().filter(|line| line.trim() != "end") {
+        writeln!(input, "{}", line)?;
+    }
+    input.push_str("end\nexit\n");
+    let entered = open()
+        .and_then(|channel| channel.run(&input, timeout))
+        .and_then(cli_errors);
+    match entered {
+        Ok(()) => open()
+            .and_then(|channel| channel.run("configure confirm\nexit\n", timeout))
+            .and_then(cli_errors)
+            .map_err(|e| {
+                format!(
+                    "failed to confirm: {}, IOS reverts in {} minutes",
+                    e, minutes
+                )
+                .into()
+            }),
+        Err(e) => {
+            let revert = open()
+                .and_then(|channel| channel.run("configure revert now\nexit\n", timeout))
+                .and_then(cli_errors)
+                .map(|()| "reverted".to_string());
+            Err(rollback_error(&e.to_string(), revert, minutes * 60).into())
+        }
+    }
+}
+
+fn rollback_error(e: &str, rollback: AppResult<String>, timeout: u32) -> String {
+    match rollback {
+        Ok(done) => format!("{}, {}", e, done),
+        Err(failed) => format!(
+            "{}, not rolled back ({}) until the {} s timeout",
+            e, failed, timeout
+        ),
+    }
+}
+
+/// The error messages in CLI output, which IOS starts with "% ".
+fn cli_errors(output: String) -> AppResult<()> {
+    let errors: Vec<&str> = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("% "))
+        .collect();
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("; ").into()),
+    }
+}
+
+/// `change` as IOS XR prefix-sets in the routing policy model, replacing
+/// those of the same names.
+pub fn xr_config(change: &Change) -> String {
+    let mut config = String::from(
+        "<routing-policy xmlns=\"http://cisco.com/ns/yang/Cisco-IOS-XR-policy-repository-cfg\">\
+         <sets><prefix-sets>",
+    );
+    for (name, entries) in change.filters {
+        let entries: Vec<String> = entries
+            .iter()
+            .filter(|entry| entry.valid)
+            .map(|entry| format!("  {}", CiscoEntryFmt(entry)))
+            .collect();
+        let rpl = match entries.is_empty() {
+            true => format!("prefix-set {}\nend-set\n", name),
+            false => format!("prefix-set {}\n{}\nend-set\n", name, entries.join(",\n")),
+        };
+        config.push_str(&format!(
+            "<prefix-set xmlns:nc=\"{}\" nc:operation=\"replace\">\
+             <set-name>{}</set-name><rpl-prefix-set>{}</rpl-prefix-set></prefix-set>",
+            netconf::BASE_NS,
+            netconf::escape(name),
+            netconf::escape(&rpl)
+        ));
+    }
+    config.push_str("</prefix-sets></sets></routing-policy>");
+    config
+}
+
+/// `change` as Junos route-filter-lists, replacing those of the same names.
+pub fn junos_config(change: &Change) -> String {
+    let mut config = String::from("<configuration><policy-options>");
+    for (name, entries) in change.filters {
+        config.push_str(&format!(
+            "<route-filter-list xmlns:nc=\"{}\" nc:operation=\"replace\"><name>{}</name>",
+            netconf::BASE_NS,
+            netconf::escape(name)
+        ));
+        for entry in entries.iter().filter(|entry| entry.valid) {
+            let range = match (entry.min == entry.mask, entry.max == entry.mask) {
+                (true, true) => "<exact/>".to_string(),
+                (true, false) => format!("<upto>/{}</upto>", entry.max),
+                _ => format!(
+                    "<prefix-length-range>/{}-/{}</prefix-length-range>",
+                    entry.min, entry.max
+                ),
+            };
+            config.push_str(&format!(
+                "<rf-list><name>{}/{}</name>{}</rf-list>",
+                entry.prefix, entry.mask, range
+            ));
+        }
+        config.push_str("</route-filter-list>");
+    }
+    config.push_str("</policy-options></configuration>");
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::netconf::{
+        elements,
+        tests::{stand_in, Received},
+        BASE_10, BASE_11, CANDIDATE, CONFIRMED_COMMIT_11,
+    };
+    use std::{
+        net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Closes the sending half of a connection when dropped, so that the
+    /// other end sees the end of the input.
+    struct Sender(TcpStream);
+
+    impl Write for Sender {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Drop for Sender {
+        fn drop(&mut self) {
+            self.0.shutdown(Shutdown::Write).ok();
+        }
+    }
+
+    fn connect(addr: SocketAddr) -> AppResult<Channel> {
+        let conn = TcpStream::connect(addr)?;
+        Ok(Channel::new(conn.try_clone()?, Sender(conn)))
+    }
+
+    /// Minimal IOS shell that reads commands until the end of its input and
+    /// complains about those containing `fail`. Records each session's input.
+    fn ios_stand_in(fail: &'static str) -> (SocketAddr, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let mut conn = conn.unwrap();
+                let mut input = String::new();
+                conn.read_to_string(&mut input).unwrap();
+                for line in input.lines() {
+                    writeln!(conn, "router#{}", line).unwrap();
+                    if !fail.is_empty() && line.contains(fail) {
+                        writeln!(conn, "% Invalid input detected at '^' marker.").unwrap();
+                    }
+                }
+                log.lock().unwrap().push(input);
+            }
+        });
+        (addr, received)
+    }
+
+    fn filters() -> Vec<(&'static str, Vec<AggPrefix>)> {
+        vec![(
+            "AS-FOO",
+            vec![
+                "192.0.2.0/24".parse().unwrap(),
+                "198.51.100.0/22 le 24".parse().unwrap(),
+                "2001:db8::/32 ge 40 le 48".parse().unwrap(),
+            ],
+        )]
+    }
+
+    #[test]
+    fn builds_netconf_configs() {
+        let filters = filters();
+        let change = Change {
+            config: "",
+            filters: &filters,
+        };
+        let xr = xr_config(&change);
+        assert_eq!(
+            elements(&xr, "rpl-prefix-set"),
+            vec![
+                "prefix-set AS-FOO\n  192.0.2.0/24,\n  198.51.100.0/22 le 24,\n  \
+                  2001:db8::/32 ge 40 le 48\nend-set\n"
+            ]
+        );
+        let junos = junos_config(&change);
+        assert_eq!(
+            elements(&junos, "rf-list"),
+            vec![
+                "<name>192.0.2.0/24</name><exact/>",
+                "<name>198.51.100.0/22</name><upto>/24</upto>",
+                "<name>2001:db8::/32</name><prefix-length-range>/40-/48</prefix-length-range>",
+            ]
+        );
+    }
+
+    const CAPABILITIES: &[&str] = &[BASE_10, BASE_11, CANDIDATE, CONFIRMED_COMMIT_11];
+
+    #[test]
+    fn commits_confirmed() {
+        let (addr, received) = stand_in(CAPABILITIES, "");
+        let filters = filters();
+        let change = Change {
+            config: "",
+            filters: &filters,
+        };
+        apply(Platform::Junos, |_| connect(addr), &change, 60).unwrap();
+        let received = received.lock().unwrap();
+        let operations: Vec<&str> = received
+            .iter()
+            .map(|rpc| &rpc[1..rpc.find(['>', '/']).unwrap()])
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                "lock",
+                "edit-config",
+                "commit",
+                "commit",
+                "unlock",
+                "close-session"
+            ]
+        );
+        assert!(received[2].contains("<confirm-timeout>60</confirm-timeout>"));
+        assert!(received[1].contains("<route-filter-list"));
+    }
+
+    #[test]
+    fn rolls_back_netconf() {
+        let filters = filters();
+        let change = Change {
+            config: "",
+            filters: &filters,
+        };
+        let (addr, received) = stand_in(CAPABILITIES, "edit-config");
+        let e = apply(Platform::Xr, |_| connect(addr), &change, 60).unwrap_err();
+        assert_eq!(e.to_string(), "'fail' <here>, changes discarded");
+        assert!(received
+            .lock()
+            .unwrap()
+            .contains(&"<discard-changes/>".to_string()));
+
+        // a confirming commit that fails
+        let (addr, received) = stand_in(CAPABILITIES, "<commit/>");
+        let e = apply(Platform::Xr, |_| connect(addr), &change, 60).unwrap_err();
+        assert_eq!(e.to_string(), "'fail' <here>, rolled back");
+        assert!(received
+            .lock()
+            .unwrap()
+            .contains(&"<cancel-commit/>".to_string()));
+
+        // the session is closed however the change fails
+        let (addr, received) = stand_in(&[BASE_10, CANDIDATE], "");
+        assert!(apply(Platform::Xr, |_| connect(addr), &change, 60).is_err());
+        assert_eq!(*received.lock().unwrap(), vec!["<close-session/>"]);
+    }
+
+    #[test]
+    fn gives_up_on_silent_routers() {
+        let filters = filters();
+        let change = Change {
+            config: "",
+            filters: &filters,
+        };
+        // connections wait in the backlog, never accepted or answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let e = apply(Platform::Junos, |_| connect(addr), &change, 1).unwrap_err();
+        assert_eq!(e.to_string(), "no answer in 1 s");
+        let e = apply(Platform::Ios, |_| connect(addr), &change, 1).unwrap_err();
+        assert!(e
+            .to_string()
+            .starts_with("no answer in 1 s, not rolled back"));
+    }
+
+    #[test]
+    fn applies_cli() {
+        let config = "no ip prefix-list AS-FOO\nip prefix-list AS-FOO permit 192.0.2.0/24\nend\n";
+        let change = Change {
+            config,
+            filters: &[],
+        };
+        let (addr, received) = ios_stand_in("");
+        apply(Platform::Ios, |_| connect(addr), &change, 90).unwrap();
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                "terminal length 0\nconfigure terminal revert timer 2\n\
+                 no ip prefix-list AS-FOO\nip prefix-list AS-FOO permit 192.0.2.0/24\nend\nexit\n",
+                "configure confirm\nexit\n",
+            ]
+        );
+
+        let (addr, received) = ios_stand_in("permit");
+        let e = apply(Platform::Ios, |_| connect(addr), &change, 90).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "% Invalid input detected at '^' marker., reverted"
+        );
+        assert_eq!(received.lock().unwrap()[1], "configure revert now\nexit\n");
+    }
+}
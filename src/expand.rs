@@ -0,0 +1,266 @@
+//! Client-side as-set expansion.
+//!
+//! Instead of letting the server recurse (`!i{set},1`), nested sets are
+//! fetched breadth-first with non-recursive `!i{set}` queries. The member
+//! graph is kept, so each as-set can be checked against depth and size limits
+//! and every AS number traced back to the sub-set that listed it.
+
+use std::convert::TryFrom;
+
+use crate::{filterclass::FilterClass, AppResult, Map, Set};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExpandLimits {
+    /// deepest level of nesting below the root set that is still expanded
+    pub max_depth: usize,
+    /// largest number of distinct AS numbers a set may expand to
+    pub max_members: usize,
+}
+
+impl Default for ExpandLimits {
+    fn default() -> Self {
+        ExpandLimits {
+            max_depth: 16,
+            max_members: 100_000,
+        }
+    }
+}
+
+/// Member graph of all fetched as-sets. Set names are upper-cased.
+#[derive(Debug, Default)]
+pub struct AsSetGraph {
+    /// AS numbers listed directly in each set
+    pub autnums: Map<String, Vec<u32>>,
+    /// as-sets listed directly in each set
+    pub sets: Map<String, Vec<String>>,
+}
+
+/// Result of expanding one as-set from the graph.
+#[derive(Debug, Default, PartialEq)]
+pub struct Expansion {
+    /// all AS numbers in the set, sorted
+    pub autnums: Vec<u32>,
+    /// shortest chain of sets from the root to the set listing each AS number
+    pub paths: Map<u32, Vec<String>>,
+    /// AS numbers contributed directly by each set in the tree
+    pub contributions: Map<String, Vec<u32>>,
+    /// loops found below the root, each given as the chain of sets that closes it
+    pub cycles: Vec<Vec<String>>,
+    /// set when a limit was exceeded; `autnums` is then incomplete
+    pub violation: Option<String>,
+}
+
+/// Fetches the graph below `roots` level by level, one pipelined batch of
+/// queries per level. Sets deeper than `max_depth` are recorded as members but
+/// not fetched.
+pub fn fetch_graph<F>(roots: &Set<&str>, max_depth: usize, mut query: F) -> AppResult<AsSetGraph>
+where
+    F: for<'q> FnMut(&Set<&'q str>) -> AppResult<Map<&'q str, Vec<String>>>,
+{
+    let mut graph = AsSetGraph::default();
+    let mut seen: Set<String> = roots.iter().map(|s| s.to_ascii_uppercase()).collect();
+    let mut frontier: Vec<String> = seen.iter().cloned().collect();
+    let mut depth = 0;
+    while !frontier.is_empty() && depth <= max_depth {
+        let batch: Set<&str> = frontier.iter().map(String::as_str).collect();
+        let mut next = Vec::new();
+        for (set, members) in query(&batch)? {
+            let autnums = graph.autnums.entry(set.to_string()).or_default();
+            let sets = graph.sets.entry(set.to_string()).or_default();
+            for member in members {
+                match FilterClass::try_from(member.as_str()) {
+                    Ok(FilterClass::AutNum(num)) => autnums.push(num),
+                    Ok(FilterClass::AsSet(name)) => {
+                        let name = name.to_ascii_uppercase();
+                        if seen.insert(name.clone()) {
+                            next.push(name.clone());
+                        }
+                        sets.push(name);
+                    }
+                    _ => return Err(format!("{}: invalid as-set member {}", set, member).into()),
+                }
+            }
+        }
+        frontier = next;
+        depth += 1;
+    }
+    Ok(graph)
+}
+
+impl AsSetGraph {
+    /// Expands `root` breadth-first, enforcing `limits`.
+    pub fn expand(&self, root: &str, limits: &ExpandLimits) -> Expansion {
+        let root = root.to_ascii_uppercase();
+        let mut ret = Expansion::default();
+        let mut parent: Map<&str, &str> = Map::new();
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back((root.as_str(), 0));
+        parent.insert(&root, "");
+        while let Some((set, depth)) = queue.pop_front() {
+            if depth > limits.max_depth {
+                ret.violation = Some(format!(
+                    "nested deeper than {} levels at {}",
+                    limits.max_depth,
+                    chain(&parent, set).join(" > ")
+                ));
+                break;
+            }
+            if !self.sets.contains_key(set) {
+                continue;
+            }
+            for num in &self.autnums[set] {
+                if !ret.paths.contains_key(num) {
+                    ret.paths.insert(*num, chain(&parent, set));
+                    ret.contributions
+                        .entry(set.to_string())
+                        .or_default()
+                        .push(*num);
+                }
+            }
+            if ret.paths.len() > limits.max_members {
+                ret.violation = Some(format!(
+                    "more than {} members after expanding {}",
+                    limits.max_members,
+                    chain(&parent, set).join(" > ")
+                ));
+                break;
+            }
+            for child in &self.sets[set] {
+                if !parent.contains_key(child.as_str()) {
+                    parent.insert(child, set);
+                    queue.push_back((child, depth + 1));
+                }
+            }
+        }
+
+        ret.cycles = self.find_cycles(&root);
+        ret.autnums = ret.paths.keys().copied().collect();
+        ret.autnums.sort_unstable();
+        ret
+    }
+
+    /// Depth-first search for back edges below `root`.
+    fn find_cycles(&self, root: &str) -> Vec<Vec<String>> {
+        let mut cycles = Vec::new();
+        let mut done: Set<&str> = Set::new();
+        let mut path: Vec<(&str, usize)> = vec![(root, 0)];
+        while let Some((set, next_child)) = path.last_mut() {
+            let children = self.sets.get(*set).map(Vec::as_slice).unwrap_or(&[]);
+            if let Some(child) = children.get(*next_child) {
+                *next_child += 1;
+                if let Some(pos) = path.iter().position(|(s, _)| s == child) {
+                    let mut cycle: Vec<String> =
+                        path[pos..].iter().map(|(s, _)| s.to_string()).collect();
+                    cycle.push(child.clone());
+                    cycles.push(cycle);
+                } else if !done.contains(child.as_str()) {
+                    path.push((child, 0));
+                }
+            } else {
+                done.insert(set);
+                path.pop();
+            }
+        }
+        cycles
+    }
+}
+
+impl Expansion {
+    /// One line per set in the tree listing the AS numbers it contributes,
+    /// or nothing when `root` lists all AS numbers itself.
+    pub fn describe_contributions(&self, root: &str) -> Vec<String> {
+        let root = root.to_ascii_uppercase();
+        if self.contributions.keys().all(|set| *set == root) {
+            return vec![];
+        }
+        let mut sets: Vec<(&String, &Vec<u32>)> = self.contributions.iter().collect();
+        sets.sort_unstable();
+        sets.into_iter()
+            .map(|(set, autnums)| {
+                let autnums: Vec<String> = autnums.iter().map(|num| format!("AS{}", num)).collect();
+                format!("Members of {} from {}: {}", root, set, autnums.join(", "))
+            })
+            .collect()
+    }
+}
+
+/// Walks `parent` links from `set` back up to the root.
+fn chain<'a>(parent: &Map<&'a str, &'a str>, mut set: &'a str) -> Vec<String> {
+    let mut chain = vec![];
+    while !set.is_empty() {
+        chain.push(set.to_string());
+        set = parent[set];
+    }
+    chain.reverse();
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> AsSetGraph {
+        let db: &[(&str, &str)] = &[
+            ("AS-ROOT", "AS1 AS-A AS-B"),
+            ("AS-A", "AS2 AS-C AS64500"),
+            ("AS-B", "AS3 AS-C"),
+            ("AS-C", "AS4 as-a"),
+        ];
+        let roots: Set<&str> = ["AS-ROOT"].iter().copied().collect();
+        fetch_graph(&roots, 16, |sets| {
+            Ok(sets
+                .iter()
+                .map(|set| {
+                    let members = db.iter().find(|(k, _)| k == set).unwrap().1;
+                    (*set, members.split_whitespace().map(String::from).collect())
+                })
+                .collect())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn expands_with_provenance() {
+        let expansion = graph().expand("as-root", &ExpandLimits::default());
+        assert_eq!(expansion.autnums, vec![1, 2, 3, 4, 64500]);
+        assert_eq!(expansion.paths[&4], vec!["AS-ROOT", "AS-A", "AS-C"]);
+        assert_eq!(expansion.contributions["AS-B"], vec![3]);
+        assert_eq!(
+            expansion.describe_contributions("as-root"),
+            vec![
+                "Members of AS-ROOT from AS-A: AS2, AS64500",
+                "Members of AS-ROOT from AS-B: AS3",
+                "Members of AS-ROOT from AS-C: AS4",
+                "Members of AS-ROOT from AS-ROOT: AS1",
+            ]
+        );
+        // AS-C lists AS4 itself, and the limit stops before AS-A
+        let flat = ExpandLimits {
+            max_depth: 0,
+            ..Default::default()
+        };
+        let expansion_c = graph().expand("AS-C", &flat);
+        assert!(expansion_c.describe_contributions("AS-C").is_empty());
+        assert_eq!(expansion.cycles, vec![vec!["AS-A", "AS-C", "AS-A"]]);
+        assert_eq!(expansion.violation, None);
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let graph = graph();
+        let shallow = ExpandLimits {
+            max_depth: 1,
+            ..Default::default()
+        };
+        let expansion = graph.expand("AS-ROOT", &shallow);
+        assert_eq!(
+            expansion.violation.as_deref(),
+            Some("nested deeper than 1 levels at AS-ROOT > AS-A > AS-C")
+        );
+        let small = ExpandLimits {
+            max_members: 2,
+            ..Default::default()
+        };
+        assert!(graph.expand("AS-ROOT", &small).violation.is_some());
+    }
+}
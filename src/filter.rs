@@ -0,0 +1,486 @@
+//! RPSL filter expressions (RFC 2622, section 5.4), as used by filter-sets.
+//!
+//! Only filters that reduce to a set of prefix ranges are supported: prefix
+//! sets such as `{192.0.2.0/24^+}`, AS numbers, as-sets, route-sets and
+//! filter-sets, AS path expressions that only constrain the origin
+//! (`<^AS1+$>`, `<AS-FOO$>`), `ANY`, and the operators `AND`, `OR` and `NOT`.
+
+use std::{convert::TryFrom, fmt};
+
+use crate::{
+    aggregate::{intersect_entries, subtract_entries, AggPrefix},
+    filterclass::FilterClass,
+    rpsl::{RangeOp, RouteSetMember, RouteSets},
+    AppResult, Map, Prefix, Set,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Any,
+    /// `{prefix^op, ...}^op`
+    Prefixes(Vec<(Prefix, RangeOp)>, RangeOp),
+    /// AS number, as-set or route-set
+    Member(RouteSetMember),
+    FilterSet(String),
+    /// AS path expression ending in one of these AS numbers or as-sets
+    Origins(Vec<RouteSetMember>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+fn tokenize(input: &str) -> AppResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let closing = match c {
+            _ if c.is_whitespace() => continue,
+            '(' => {
+                tokens.push(Token::LParen);
+                continue;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                continue;
+            }
+            '{' => Some('}'),
+            '<' => Some('>'),
+            _ => None,
+        };
+        if let Some(closing) = closing {
+            chars
+                .by_ref()
+                .find(|(_, c)| *c == closing)
+                .ok_or_else(|| format!("missing {} in {}", closing, input))?;
+        }
+        // up to the next separator, including a range operator after `}`
+        let mut end = input.len();
+        while let Some((pos, c)) = chars.peek() {
+            if c.is_whitespace() || *c == '(' || *c == ')' {
+                end = *pos;
+                break;
+            }
+            chars.next();
+        }
+        let term = &input[start..end];
+        tokens.push(match term.to_ascii_uppercase().as_str() {
+            "AND" => Token::And,
+            "OR" => Token::Or,
+            "NOT" => Token::Not,
+            _ => Token::Term(term.to_string()),
+        });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1).cloned()
+    }
+
+    /// Terms next to each other without an operator are ORed.
+    fn or_expr(&mut self) -> AppResult<Filter> {
+        let mut filter = self.and_expr()?;
+        loop {
+            match self.peek() {
+                Some(Token::Or) => {
+                    self.next();
+                }
+                Some(Token::LParen) | Some(Token::Not) | Some(Token::Term(_)) => (),
+                _ => return Ok(filter),
+            }
+            filter = Filter::Or(Box::new(filter), Box::new(self.and_expr()?));
+        }
+    }
+
+    fn and_expr(&mut self) -> AppResult<Filter> {
+        let mut filter = self.not_expr()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            filter = Filter::And(Box::new(filter), Box::new(self.not_expr()?));
+        }
+        Ok(filter)
+    }
+
+    fn not_expr(&mut self) -> AppResult<Filter> {
+        match self.next() {
+            Some(Token::Not) => Ok(Filter::Not(Box::new(self.not_expr()?))),
+            Some(Token::LParen) => {
+                let filter = self.or_expr()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(filter),
+                    _ => Err("missing )".into()),
+                }
+            }
+            Some(Token::Term(term)) => parse_term(&term),
+            Some(token) => Err(format!("unexpected {:?}", token).into()),
+            None => Err("unexpected end of filter".into()),
+        }
+    }
+}
+
+fn parse_term(term: &str) -> AppResult<Filter> {
+    if term.eq_ignore_ascii_case("ANY") {
+        return Ok(Filter::Any);
+    }
+    if term.starts_with('{') {
+        return parse_prefix_set(term);
+    }
+    if term.starts_with('<') {
+        return parse_path(term);
+    }
+    let name = term.split('^').next().unwrap_or("");
+    match FilterClass::try_from(name) {
+        Ok(FilterClass::FilterSet(_)) if name == term => Ok(Filter::FilterSet(name.to_string())),
+        Ok(FilterClass::AutNum(_)) | Ok(FilterClass::AsSet(_)) | Ok(FilterClass::RouteSet(_)) => {
+            Ok(Filter::Member(RouteSetMember::parse(term)?))
+        }
+        _ => Err(format!("unsupported filter term {}", term).into()),
+    }
+}
+
+/// Parses `{192.0.2.0/24^+, 198.51.100.0/24}^-`.
+fn parse_prefix_set(term: &str) -> AppResult<Filter> {
+    let close = term.find('}').ok_or("missing }")?;
+    let op = match &term[close + 1..] {
+        "" => RangeOp::None,
+        op => RangeOp::parse(op.strip_prefix('^').ok_or("invalid range operator")?)?,
+    };
+    let prefixes = term[1..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|elem| !elem.is_empty())
+        .map(|elem| match RouteSetMember::parse(elem)? {
+            RouteSetMember::Prefix(prefix, op) => Ok((prefix, op)),
+            _ => Err(format!("invalid prefix {}", elem).into()),
+        })
+        .collect::<AppResult<_>>()?;
+    Ok(Filter::Prefixes(prefixes, op))
+}
+
+/// Reduces an AS path expression to the origins it permits, if it only
+/// constrains the last AS: `<AS1$>`, `<^AS1+$>`, `<.* [AS1 AS-FOO]$>`.
+fn parse_path(term: &str) -> AppResult<Filter> {
+    let err = || format!("AS path {} does not reduce to origin ASes", term);
+    let inner = term
+        .strip_prefix('<')
+        .and_then(|path| path.strip_suffix('>'))
+        .and_then(|path| path.trim().trim_start_matches('^').strip_suffix('$'))
+        .ok_or_else(err)?
+        .trim();
+    let inner = inner.strip_prefix(".*").unwrap_or(inner).trim();
+    let inner = inner.strip_suffix('+').unwrap_or(inner);
+    let names: Vec<&str> = match inner.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(list) => list.split_whitespace().collect(),
+        None if !inner.contains(char::is_whitespace) => vec![inner],
+        None => return Err(err().into()),
+    };
+    let origins = names
+        .into_iter()
+        .map(|name| match FilterClass::try_from(name) {
+            Ok(FilterClass::AutNum(num)) => Ok(RouteSetMember::AutNum(num, RangeOp::None)),
+            Ok(FilterClass::AsSet(set)) => {
+                Ok(RouteSetMember::AsSet(set.to_string(), RangeOp::None))
+            }
+            _ => Err(err()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if origins.is_empty() {
+        return Err(err().into());
+    }
+    Ok(Filter::Origins(origins))
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> AppResult<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        let filter = parser
+            .or_expr()
+            .map_err(|e| format!("invalid filter {}: {}", input, e))?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(format!("invalid filter {}: unexpected {:?}", input, token).into()),
+        }
+    }
+
+    /// Every AS number, as-set, route-set and filter-set the filter refers to.
+    pub fn references<'a>(&'a self, refs: &mut FilterRefs<'a>) {
+        match self {
+            Filter::Any | Filter::Prefixes(..) => (),
+            Filter::Member(member) => refs.add(member),
+            Filter::Origins(members) => members.iter().for_each(|member| refs.add(member)),
+            Filter::FilterSet(name) => {
+                refs.filter_sets.insert(name);
+            }
+            Filter::And(a, b) | Filter::Or(a, b) => {
+                a.references(refs);
+                b.references(refs);
+            }
+            Filter::Not(a) => a.references(refs),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // operands of a different binary operator are parenthesized, so
+        // precedence never matters
+        let operand = |filter: &Filter| match (self, filter) {
+            (Filter::And(..), Filter::And(..)) | (Filter::Or(..), Filter::Or(..)) => {
+                filter.to_string()
+            }
+            (_, Filter::And(..)) | (_, Filter::Or(..)) => format!("({})", filter),
+            _ => filter.to_string(),
+        };
+        match self {
+            Filter::Any => f.write_str("ANY"),
+            Filter::Prefixes(prefixes, op) => {
+                let prefixes: Vec<String> = prefixes
+                    .iter()
+                    .map(|(prefix, op)| RouteSetMember::Prefix(*prefix, *op).to_string())
+                    .collect();
+                write!(f, "{{{}}}{}", prefixes.join(", "), op)
+            }
+            Filter::Member(member) => write!(f, "{}", member),
+            Filter::FilterSet(name) => f.write_str(name),
+            Filter::Origins(origins) => {
+                let origins: Vec<String> = origins.iter().map(ToString::to_string).collect();
+                write!(f, "<[{}]$>", origins.join(" "))
+            }
+            Filter::And(a, b) => write!(f, "{} AND {}", operand(a), operand(b)),
+            Filter::Or(a, b) => write!(f, "{} OR {}", operand(a), operand(b)),
+            Filter::Not(a) => write!(f, "NOT {}", operand(a)),
+        }
+    }
+}
+
+/// Objects referred to by filter expressions.
+#[derive(Debug, Default)]
+pub struct FilterRefs<'a> {
+    pub autnums: Set<u32>,
+    pub as_sets: Set<&'a str>,
+    pub route_sets: Set<&'a str>,
+    pub filter_sets: Set<&'a str>,
+}
+
+impl<'a> FilterRefs<'a> {
+    fn add(&mut self, member: &'a RouteSetMember) {
+        match member {
+            RouteSetMember::Prefix(..) => (),
+            RouteSetMember::AutNum(num, _) => {
+                self.autnums.insert(*num);
+            }
+            RouteSetMember::AsSet(name, _) => {
+                self.as_sets.insert(name);
+            }
+            RouteSetMember::RouteSet(name, _) => {
+                self.route_sets.insert(name);
+            }
+        }
+    }
+}
+
+/// Fetches and parses `roots` and every filter-set they refer to.
+///
+/// `query` returns the filter expression of each filter-set it finds. A
+/// filter-set that fails to parse is kept as its error.
+pub fn fetch_filter_sets<F>(
+    roots: &Set<&str>,
+    mut query: F,
+) -> AppResult<Map<String, Result<Filter, String>>>
+where
+    F: for<'q> FnMut(&Set<&'q str>) -> AppResult<Map<&'q str, String>>,
+{
+    let mut filter_sets: Map<String, Result<Filter, String>> = Map::new();
+    let mut seen: Set<String> = roots.iter().map(|s| s.to_ascii_uppercase()).collect();
+    let mut frontier: Vec<String> = roots.iter().map(|s| s.to_string()).collect();
+    while !frontier.is_empty() {
+        let batch: Set<&str> = frontier.iter().map(String::as_str).collect();
+        let mut next = Vec::new();
+        for (set, text) in query(&batch)? {
+            let filter = Filter::parse(&text).map_err(|e| e.to_string());
+            if let Ok(filter) = filter.as_ref() {
+                let mut refs = FilterRefs::default();
+                filter.references(&mut refs);
+                for name in refs.filter_sets {
+                    if seen.insert(name.to_ascii_uppercase()) {
+                        next.push(name.to_string());
+                    }
+                }
+            }
+            filter_sets.insert(set.to_string(), filter);
+        }
+        frontier = next;
+    }
+    Ok(filter_sets)
+}
+
+/// The data filter expressions are evaluated against.
+pub struct FilterSets<'a> {
+    /// filter-sets as returned by [`fetch_filter_sets`]
+    pub filter_sets: &'a Map<String, Result<Filter, String>>,
+    /// route-sets, as-sets and AS numbers the filters refer to
+    pub route_sets: RouteSets<'a>,
+}
+
+impl FilterSets<'_> {
+    /// Filter entries permitted by filter-set `name`.
+    pub fn eval(&self, name: &str) -> AppResult<Vec<AggPrefix>> {
+        self.eval_set(name, &mut Set::new())
+    }
+
+    /// Filter entries permitted by `filter`.
+    pub fn eval_filter(&self, filter: &Filter) -> AppResult<Vec<AggPrefix>> {
+        self.eval_inner(filter, &mut Set::new())
+    }
+
+    fn eval_set(&self, name: &str, seen: &mut Set<String>) -> AppResult<Vec<AggPrefix>> {
+        if !seen.insert(name.to_ascii_uppercase()) {
+            return Err(format!("filter-set {} refers to itself", name).into());
+        }
+        let filter = self
+            .filter_sets
+            .iter()
+            .find(|(set, _)| set.eq_ignore_ascii_case(name))
+            .map(|(_, filter)| filter)
+            .ok_or_else(|| format!("filter-set {} not found", name))?
+            .as_ref()
+            .map_err(|e| format!("{}: {}", name, e))?;
+        let entries = self.eval_inner(filter, seen)?;
+        seen.remove(&name.to_ascii_uppercase());
+        Ok(entries)
+    }
+
+    fn eval_inner(&self, filter: &Filter, seen: &mut Set<String>) -> AppResult<Vec<AggPrefix>> {
+        let any = || -> Vec<AggPrefix> {
+            vec![
+                "0.0.0.0/0 le 32".parse().expect("BUG: invalid entry"),
+                "::/0 le 128".parse().expect("BUG: invalid entry"),
+            ]
+        };
+        let members = |members: &[RouteSetMember]| -> Vec<AggPrefix> {
+            members
+                .iter()
+                .flat_map(|member| self.route_sets.member_entries(member))
+                .map(|(_, entry)| entry)
+                .collect()
+        };
+        Ok(match filter {
+            Filter::Any => any(),
+            Filter::Prefixes(prefixes, outer) => prefixes
+                .iter()
+                .filter_map(|(prefix, op)| op.apply(&AggPrefix::from_prefix(prefix)))
+                .filter_map(|entry| outer.apply(&entry))
+                .collect(),
+            Filter::Member(member) => members(std::slice::from_ref(member)),
+            Filter::Origins(origins) => members(origins),
+            Filter::FilterSet(name) => self.eval_set(name, seen)?,
+            Filter::Or(a, b) => {
+                let mut entries = self.eval_inner(a, seen)?;
+                entries.extend(self.eval_inner(b, seen)?);
+                entries
+            }
+            Filter::And(a, b) => match (a.as_ref(), b.as_ref()) {
+                (a, Filter::Not(b)) | (Filter::Not(b), a) => {
+                    subtract_entries(self.eval_inner(a, seen)?, &self.eval_inner(b, seen)?)
+                }
+                (a, b) => intersect_entries(&self.eval_inner(a, seen)?, &self.eval_inner(b, seen)?),
+            },
+            Filter::Not(a) => subtract_entries(any(), &self.eval_inner(a, seen)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irr::parse_prefix;
+
+    #[test]
+    fn parses_filters() {
+        let parse = |s| Filter::parse(s).unwrap().to_string();
+        assert_eq!(parse("AS1 OR AS-FOO"), "AS1 OR AS-FOO");
+        assert_eq!(parse("AS1 AS2 AND NOT AS3"), "AS1 OR (AS2 AND NOT AS3)");
+        assert_eq!(
+            parse("(rs-foo^+ or {192.0.2.0/24^-, 10.0.0.0/8}^24) and not fltr-bogons"),
+            "(rs-foo^+ OR {192.0.2.0/24^-, 10.0.0.0/8}^24) AND NOT fltr-bogons"
+        );
+        assert_eq!(parse("<^AS1+$>"), "<[AS1]$>");
+        assert_eq!(parse("<.* [AS1 AS-FOO]$>"), "<[AS1 AS-FOO]$>");
+        assert!(Filter::parse("<^AS1 AS2$>").is_err());
+        assert!(Filter::parse("AS1 AND").is_err());
+        assert!(Filter::parse("community(65535:666)").is_err());
+    }
+
+    #[test]
+    fn evaluates_filters() {
+        let text: Map<&str, &str> = vec![
+            (
+                "FLTR-CUSTOMER",
+                "(AS64500 OR <^AS-FOO+$>) AND NOT FLTR-MARTIAN",
+            ),
+            ("FLTR-MARTIAN", "{10.0.0.0/8^+}"),
+        ]
+        .into_iter()
+        .collect();
+        let roots: Set<&str> = ["FLTR-CUSTOMER"].iter().copied().collect();
+        let filter_sets = fetch_filter_sets(&roots, |sets| {
+            Ok(sets
+                .iter()
+                .map(|set| (*set, text[set].to_string()))
+                .collect())
+        })
+        .unwrap();
+        assert_eq!(filter_sets.len(), 2);
+
+        let prefix = |s| parse_prefix(s).unwrap();
+        let as_sets: Map<&str, Vec<u32>> = vec![("AS-FOO", vec![64501])].into_iter().collect();
+        let autnums: Map<u32, Vec<Prefix>> = vec![
+            (64500, vec![prefix("192.0.2.0/24"), prefix("10.1.0.0/16")]),
+            (64501, vec![prefix("198.51.100.0/24")]),
+        ]
+        .into_iter()
+        .collect();
+        let filters = FilterSets {
+            filter_sets: &filter_sets,
+            route_sets: RouteSets {
+                route_sets: &Map::new(),
+                as_sets: &as_sets,
+                autnums: &autnums,
+            },
+        };
+        let mut entries = filters.eval("fltr-customer").unwrap();
+        entries.sort_unstable();
+        assert_eq!(
+            entries,
+            vec![
+                AggPrefix::from_prefix(&prefix("192.0.2.0/24")),
+                AggPrefix::from_prefix(&prefix("198.51.100.0/24")),
+            ]
+        );
+        assert!(filters.eval("FLTR-MISSING").is_err());
+    }
+}
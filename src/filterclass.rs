@@ -4,13 +4,29 @@ use std::{convert::TryFrom, error};
 pub enum FilterClass<'a> {
     AsSet(&'a str),
     RouteSet(&'a str),
+    FilterSet(&'a str),
     AutNum(u32),
+    /// `policy:AS1->AS2`: what AS1's aut-num says it exports to AS2
+    Policy(u32, u32),
 }
 
 impl<'a> TryFrom<&'a str> for FilterClass<'a> {
     type Error = Box<dyn error::Error>;
 
     fn try_from(input: &'a str) -> Result<FilterClass<'a>, Self::Error> {
+        if let Some(policy) = input
+            .get(0..7)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("policy:"))
+            .map(|_| &input[7..])
+        {
+            let mut autnums = policy.splitn(2, "->").map(parse_name_component);
+            return match (autnums.next(), autnums.next()) {
+                (Some(Ok(FilterClass::AutNum(from))), Some(Ok(FilterClass::AutNum(to)))) => {
+                    Ok(FilterClass::Policy(from, to))
+                }
+                _ => Err(input.into()),
+            };
+        }
         if input.contains(':') {
             // From RFC 2622:
             //   Set names can also be hierarchical.  A hierarchical set name is a
@@ -23,9 +39,10 @@ impl<'a> TryFrom<&'a str> for FilterClass<'a> {
             let elems = input.split(':');
             for elem in elems {
                 match parse_name_component(elem) {
-                    Ok(FilterClass::AutNum(_)) | Err(_) => continue,
+                    Ok(FilterClass::AutNum(_)) | Ok(FilterClass::Policy(..)) | Err(_) => continue,
                     Ok(FilterClass::AsSet(_)) => return Ok(FilterClass::AsSet(input)),
                     Ok(FilterClass::RouteSet(_)) => return Ok(FilterClass::RouteSet(input)),
+                    Ok(FilterClass::FilterSet(_)) => return Ok(FilterClass::FilterSet(input)),
                 }
             }
             Err(input.into())
@@ -35,10 +52,13 @@ impl<'a> TryFrom<&'a str> for FilterClass<'a> {
     }
 }
 
-fn parse_name_component(input: &str) -> Result<FilterClass, Box<dyn error::Error>> {
+fn parse_name_component(input: &str) -> Result<FilterClass<'_>, Box<dyn error::Error>> {
     match input.get(0..3) {
         Some(name) if name.eq_ignore_ascii_case("as-") => Ok(FilterClass::AsSet(input)),
         Some(name) if name.eq_ignore_ascii_case("rs-") => Ok(FilterClass::RouteSet(input)),
+        _ if matches!(input.get(0..5), Some(name) if name.eq_ignore_ascii_case("fltr-")) => {
+            Ok(FilterClass::FilterSet(input))
+        }
         Some(name) if name[..2].eq_ignore_ascii_case("as") => input[2..]
             .parse::<u32>()
             .map(FilterClass::AutNum)
@@ -67,13 +87,13 @@ mod tests {
                 .map(BufReader::new)
                 .unwrap()
                 .lines()
-                .filter_map(Result::ok)
+                .map_while(Result::ok)
                 .filter(|l| l.starts_with("route-set:"))
                 .for_each(|line| {
                     let name = line.split_whitespace().nth(1).unwrap();
                     match FilterClass::try_from(name).unwrap() {
                         FilterClass::RouteSet(_) => num_parsed += 1,
-                        _ => panic!(name.to_owned()),
+                        _ => panic!("{}", name),
                     }
                 });
             eprintln!("All {} route-set names parsed correctly", num_parsed);
@@ -87,13 +107,13 @@ mod tests {
                 .map(BufReader::new)
                 .unwrap()
                 .lines()
-                .filter_map(Result::ok)
+                .map_while(Result::ok)
                 .filter(|l| l.starts_with("as-set:"))
                 .for_each(|line| {
                     let name = line.split_whitespace().nth(1).unwrap();
                     match FilterClass::try_from(name).unwrap() {
                         FilterClass::AsSet(_) => num_parsed += 1,
-                        _ => panic!(name.to_owned()),
+                        _ => panic!("{}", name),
                     }
                 });
             eprintln!("All {} as-set names parsed correctly", num_parsed);
@@ -1,10 +1,87 @@
-use std::fmt::{Display, Formatter, Result};
+use std::{
+    fmt::{Display, Formatter, Result},
+    str::FromStr,
+};
 
-use crate::aggregate::AggPrefix;
+use crate::{aggregate::AggPrefix, AppResult, Map};
 
-pub struct CiscoPrefixList<'a>(pub &'a str, pub &'a str, pub &'a [AggPrefix]);
-pub struct CiscoPrefixSet<'a>(pub &'a str, pub &'a str, pub &'a [AggPrefix]);
-pub struct CiscoEntryFmt<'a>(&'a AggPrefix);
+/// Output configuration styles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Style {
+    /// IOS `ip prefix-list` and `ipv6 prefix-list`
+    PrefixList,
+    /// IOS-XR `prefix-set`
+    PrefixSet,
+}
+
+impl FromStr for Style {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "prefix-list" => Ok(Style::PrefixList),
+            "prefix-set" => Ok(Style::PrefixSet),
+            _ => Err(format!("unknown output style {}", s).into()),
+        }
+    }
+}
+
+impl Display for Style {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.write_str(match self {
+            Style::PrefixList => "prefix-list",
+            Style::PrefixSet => "prefix-set",
+        })
+    }
+}
+
+impl Style {
+    fn allows(self, c: char) -> bool {
+        match self {
+            // a single CLI word; `?` would ask for help instead
+            Style::PrefixList => c.is_ascii_graphic() && c != '?',
+            // RPL object names
+            Style::PrefixSet => c.is_ascii_alphanumeric() || c == '_' || c == '-',
+        }
+    }
+
+    /// Rejects names the router would not accept.
+    pub fn check_name(self, name: &str) -> AppResult<()> {
+        if name.is_empty() {
+            return Err(format!("empty {} name", self).into());
+        }
+        match name.chars().find(|c| !self.allows(*c)) {
+            Some(c) => Err(format!("{:?} is not allowed in {} name {}", c, self, name).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// `name` with every character the router would reject replaced by `_`,
+    /// e.g. `AS1:AS-FOO` becomes `AS1_AS-FOO` in a prefix-set.
+    pub fn sanitize_name(self, name: &str) -> String {
+        name.chars()
+            .map(|c| if self.allows(c) { c } else { '_' })
+            .collect()
+    }
+}
+
+/// IPv4 and IPv6 names, comment, entries and notes (extra comment lines) of
+/// a prefix-list.
+pub struct CiscoPrefixList<'a>(
+    pub (&'a str, &'a str),
+    pub &'a str,
+    pub &'a [AggPrefix],
+    pub &'a [String],
+);
+/// IPv4 and IPv6 names, comment, entries and notes (extra comment lines) of
+/// a prefix-set. With different names, each family gets its own prefix-set.
+pub struct CiscoPrefixSet<'a>(
+    pub (&'a str, &'a str),
+    pub &'a str,
+    pub &'a [AggPrefix],
+    pub &'a [String],
+);
+pub struct CiscoEntryFmt<'a>(pub &'a AggPrefix);
 
 impl<'a> Display for CiscoEntryFmt<'a> {
     fn fmt(&self, f: &mut Formatter) -> Result {
@@ -25,22 +102,26 @@ impl<'a> Display for CiscoEntryFmt<'a> {
 
 impl<'a> Display for CiscoPrefixList<'a> {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        let (name, comment, list) = (self.0, self.1, self.2);
+        let ((v4_name, v6_name), comment, list, notes) = (self.0, self.1, self.2, self.3);
         writeln!(
             f,
-            "no ip prefix-list {name}\n\
-             ip prefix-list {name} description {comment}\n\
-             no ipv6 prefix-list {name}\n\
-             ipv6 prefix-list {name} description {comment}",
-            name = name,
+            "no ip prefix-list {v4_name}\n\
+             ip prefix-list {v4_name} description {comment}\n\
+             no ipv6 prefix-list {v6_name}\n\
+             ipv6 prefix-list {v6_name} description {comment}",
+            v4_name = v4_name,
+            v6_name = v6_name,
             comment = comment,
         )?;
+        for note in notes.iter() {
+            writeln!(f, "! {}", note)?;
+        }
         for prefix in list.iter() {
             assert!(prefix.valid);
-            let family = if prefix.prefix.is_ipv4() {
-                "ip"
+            let (family, name) = if prefix.prefix.is_ipv4() {
+                ("ip", v4_name)
             } else {
-                "ipv6"
+                ("ipv6", v6_name)
             };
             let prefix = CiscoEntryFmt(prefix);
             writeln!(f, "{} prefix-list {} permit {}", family, name, prefix)?;
@@ -51,14 +132,33 @@ impl<'a> Display for CiscoPrefixList<'a> {
 
 impl<'a> Display for CiscoPrefixSet<'a> {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        let (name, comment, list) = (self.0, self.1, self.2);
+        let ((v4_name, v6_name), comment, list, notes) = (self.0, self.1, self.2, self.3);
+        if v4_name != v6_name {
+            let (v4, v6): (Vec<AggPrefix>, Vec<AggPrefix>) = list
+                .iter()
+                .cloned()
+                .partition(|prefix| prefix.prefix.is_ipv4());
+            write!(
+                f,
+                "{}",
+                CiscoPrefixSet((v4_name, v4_name), comment, &v4, notes)
+            )?;
+            return write!(
+                f,
+                "{}",
+                CiscoPrefixSet((v6_name, v6_name), comment, &v6, notes)
+            );
+        }
         writeln!(
             f,
             "no prefix-set {name}\n\
              prefix-set {name}\n # {comment}",
-            name = name,
+            name = v4_name,
             comment = comment
         )?;
+        for note in notes.iter() {
+            writeln!(f, " # {}", note)?;
+        }
         let mut first = true;
         for prefix in list.iter().map(CiscoEntryFmt) {
             if first {
@@ -71,3 +171,95 @@ impl<'a> Display for CiscoPrefixSet<'a> {
         writeln!(f, "\nend-set")
     }
 }
+
+/// Entries of each prefix-list and prefix-set in a configuration written
+/// with `CiscoPrefixList` or `CiscoPrefixSet`, by family ("ip" or "ipv6", and
+/// "" for prefix-sets) and name. Other lines are ignored.
+pub fn read_filters(config: &str) -> Map<(&'static str, String), Vec<AggPrefix>> {
+    let mut filters: Map<(&'static str, String), Vec<AggPrefix>> = Map::new();
+    let mut prefix_set: Option<String> = None;
+    for line in config.lines() {
+        let mut words = line.split_whitespace();
+        if let Some(name) = prefix_set.as_ref() {
+            match line.trim().trim_end_matches(',') {
+                "end-set" => prefix_set = None,
+                entry => {
+                    if let Ok(entry) = entry.parse() {
+                        filters.entry(("", name.clone())).or_default().push(entry);
+                    }
+                }
+            }
+            continue;
+        }
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some("prefix-set"), Some(name), None, None) => {
+                filters.entry(("", name.to_string())).or_default();
+                prefix_set = Some(name.to_string());
+            }
+            (Some(family), Some("prefix-list"), Some(name), Some(action)) => {
+                let family = match family {
+                    "ip" => "ip",
+                    "ipv6" => "ipv6",
+                    _ => continue,
+                };
+                let entries = filters.entry((family, name.to_string())).or_default();
+                if action == "permit" {
+                    let entry = words.collect::<Vec<_>>().join(" ");
+                    if let Ok(entry) = entry.parse() {
+                        entries.push(entry);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_names() {
+        assert_eq!(Style::PrefixSet.sanitize_name("AS1:AS-FOO"), "AS1_AS-FOO");
+        assert_eq!(Style::PrefixList.sanitize_name("AS1:AS-FOO"), "AS1:AS-FOO");
+        assert!(Style::PrefixSet.check_name("CUST-ACME-IN-V4").is_ok());
+        assert!(Style::PrefixSet.check_name("AS1:AS-FOO").is_err());
+        assert!(Style::PrefixList.check_name("CUST ACME").is_err());
+        assert!(Style::PrefixList.check_name("").is_err());
+    }
+
+    #[test]
+    fn splits_families() {
+        let entries: Vec<AggPrefix> = vec![
+            "192.0.2.0/24".parse().unwrap(),
+            "2001:db8::/32".parse().unwrap(),
+        ];
+        let text = CiscoPrefixSet(("C-V4", "C-V6"), "c", &entries, &[]).to_string();
+        assert_eq!(
+            text,
+            "no prefix-set C-V4\nprefix-set C-V4\n # c\n 192.0.2.0/24\nend-set\n\
+             no prefix-set C-V6\nprefix-set C-V6\n # c\n 2001:db8::/32\nend-set\n"
+        );
+        let text = CiscoPrefixList(("C-V4", "C-V6"), "c", &entries, &[]).to_string();
+        assert!(text.contains("ipv6 prefix-list C-V6 permit 2001:db8::/32\n"));
+    }
+
+    #[test]
+    fn reads_filters_back() {
+        let entries: Vec<AggPrefix> = vec![
+            "192.0.2.0/24 le 26".parse().unwrap(),
+            "198.51.100.0/24".parse().unwrap(),
+            "2001:db8::/32 ge 48".parse().unwrap(),
+        ];
+        let notes = ["dropped bogon 10.0.0.0/8".to_string()];
+        let list = CiscoPrefixList(("L4", "L6"), "c", &entries, &notes).to_string();
+        let set = CiscoPrefixSet(("S", "S"), "c", &entries, &notes).to_string();
+        let filters = read_filters(&format!("{}{}end\n", list, set));
+        assert_eq!(filters.len(), 3);
+        assert_eq!(filters[&("ip", "L4".to_string())], entries[..2]);
+        assert_eq!(filters[&("ipv6", "L6".to_string())], entries[2..]);
+        assert_eq!(filters[&("", "S".to_string())], entries);
+    }
+}
@@ -1,10 +1,12 @@
 use std::{
+    hash::Hash,
     io::{self, prelude::*, Error, ErrorKind::*},
-    net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
     time::Duration,
 };
 
-use crate::{AppResult, Map, Prefix, Set};
+use crate::{rpsl::RouteSetMember, AppResult, Map, Prefix, Set};
 use bufstream::BufStream;
 
 /// IRR client.
@@ -15,6 +17,8 @@ use bufstream::BufStream;
 pub struct IrrClient {
     stream: BufStream<TcpStream>,
     buf: Vec<u8>,
+    /// comma separated source databases selected with `!s`
+    sources: String,
 }
 
 const TIMEOUT: Duration = Duration::from_secs(30);
@@ -30,6 +34,7 @@ impl IrrClient {
                     let mut client = IrrClient {
                         stream: BufStream::new(conn),
                         buf: Vec::with_capacity(4096),
+                        sources: sources.to_string(),
                     };
                     writeln!(
                         client.stream,
@@ -59,57 +64,22 @@ impl IrrClient {
         let mut reply: Option<String> = None;
         loop {
             self.buf.clear();
-            let len = self.stream.read_until(b'\n', &mut self.buf)? - 1;
-            match &self.buf[..len].split_first() {
-                // successful query returning data
-                Some((b'A', data)) => {
-                    let len_bytes = data;
-                    let content_len: usize = std::str::from_utf8(len_bytes)
-                        .map_err(|e| Error::new(InvalidData, e))
-                        .and_then(|s| s.parse().map_err(|e| Error::new(InvalidData, e)))?;
+            self.stream.read_until(b'\n', &mut self.buf)?;
+            match parse_status(&self.buf)? {
+                Status::Data(content_len) => {
                     self.buf.resize(content_len, 0);
                     self.stream.read_exact(&mut self.buf)?;
                     let content = String::from_utf8(self.buf.clone())
                         .map_err(|e| Error::new(InvalidData, e))?;
                     reply = Some(content);
                 }
-                // successful query returning no data
-                Some((b'C', &[])) => {
+                Status::Done => {
                     if reply.is_some() {
                         return Ok(reply);
                     }
                 }
-                // unsuccessful query - Key not found
-                Some((b'D', &[])) => {
-                    return Ok(None);
-                }
-                // unsuccessful query - There are multiple copies of the key in one database
-                Some((b'E', &[])) => {
-                    return Err(Error::new(
-                        Other,
-                        "There are multiple copies of the key in one database",
-                    )
-                    .into())
-                }
-                // other error
-                Some((b'F', data)) => {
-                    return Err(Error::new(Other, String::from_utf8_lossy(data)).into());
-                }
-                Some((code, data)) => {
-                    return Err(Error::new(
-                        InvalidData,
-                        format!(
-                            "invalid reply: {:?} => {:?}",
-                            char::from(**code),
-                            String::from_utf8_lossy(data)
-                        ),
-                    )
-                    .into())
-                }
-                None => {
-                    return Err(Error::new(Other, "short reply").into());
-                }
-            };
+                Status::NotFound => return Ok(None),
+            }
         }
     }
 
@@ -124,15 +94,50 @@ impl IrrClient {
         }
         self.stream.flush()?;
         for set in iter.clone() {
-            let autnums = ret.entry(set).or_insert_with(Vec::new);
+            let autnums = ret.entry(set).or_default();
             if let Some(reply) = self.read_reply()? {
-                for autnum in reply.split_whitespace().map(|s| parse_autnum(s)) {
-                    match autnum? {
-                        // skip invalid/private AS numbers
-                        0 | 23_456 | 64_496..=65_535 | 4_200_000_000..=4_294_967_294 => continue,
-                        valid => autnums.push(valid),
-                    }
-                }
+                autnums.extend(parse_as_set_reply(&reply)?);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Fetches the direct members of each set (`!i{set}`), without recursing
+    /// into nested sets.
+    pub fn resolve_set_members<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<String>>> {
+        let iter = sets.iter();
+        let mut ret: Map<&str, Vec<String>> = Map::new();
+        for set in iter.clone() {
+            writeln!(self.stream, "!i{}", set)?;
+        }
+        self.stream.flush()?;
+        for set in iter.clone() {
+            let members = ret.entry(*set).or_default();
+            if let Some(reply) = self.read_reply()? {
+                members.extend(reply.split_whitespace().map(str::to_string));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Fetches the route objects registered for exactly each prefix (`!r{prefix}`).
+    pub fn resolve_route_objects(
+        &mut self,
+        prefixes: &Set<Prefix>,
+    ) -> AppResult<Map<Prefix, Vec<RouteObject>>> {
+        let iter = prefixes.iter();
+        let mut ret: Map<Prefix, Vec<RouteObject>> = Map::new();
+        for (ip, masklen) in iter.clone() {
+            writeln!(self.stream, "!r{}/{}", ip, masklen)?;
+        }
+        self.stream.flush()?;
+        for prefix in iter.clone() {
+            let objects = ret.entry(*prefix).or_default();
+            if let Some(reply) = self.read_reply()? {
+                objects.extend(parse_route_objects(&reply)?);
             }
         }
         Ok(ret)
@@ -140,26 +145,55 @@ impl IrrClient {
 
     pub fn resolve_route_sets<'a>(
         &mut self,
-        sets: &'a Set<&str>,
-    ) -> AppResult<Map<&'a str, Vec<Prefix>>> {
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<RouteSetMember>>> {
         let iter = sets.iter();
-        let mut ret: Map<&str, Vec<Prefix>> = Map::new();
+        let mut ret: Map<&str, Vec<RouteSetMember>> = Map::new();
         for set in iter.clone() {
             writeln!(self.stream, "!i{},1", set)?;
         }
         self.stream.flush()?;
         for set in iter.clone() {
-            let prefixlist = ret.entry(*set).or_insert_with(Vec::new);
+            let prefixlist = ret.entry(*set).or_default();
             if let Some(reply) = self.read_reply()? {
-                for elem in reply.split_whitespace() {
-                    let prefix = parse_prefix(elem)?;
-                    prefixlist.push(prefix);
-                }
+                prefixlist.extend(parse_route_set_reply(&reply)?);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Fetches the RPSL text of objects of class `class`, e.g. `aut-num`;
+    /// missing objects are left out.
+    pub fn resolve_objects<'a>(
+        &mut self,
+        class: &str,
+        keys: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        let iter = keys.iter();
+        let mut ret: Map<&str, String> = Map::new();
+        for key in iter.clone() {
+            writeln!(self.stream, "!m{},{}", class, key)?;
+        }
+        self.stream.flush()?;
+        for key in iter.clone() {
+            if let Some(reply) = self.read_reply()? {
+                ret.insert(*key, reply);
             }
         }
         Ok(ret)
     }
 
+    /// Fetches the filter expression of each filter-set; missing sets are left out.
+    pub fn resolve_filter_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        self.resolve_objects("filter-set", sets)?
+            .into_iter()
+            .map(|(set, text)| Ok((set, parse_filter_set(&text)?)))
+            .collect()
+    }
+
     pub fn resolve_autnums(&mut self, autnums: &Set<u32>) -> AppResult<Map<u32, Vec<Prefix>>> {
         let iter = autnums.iter();
         for autnum in iter.clone() {
@@ -174,21 +208,51 @@ impl IrrClient {
             let prefixlist = ret.entry(*autnum).or_insert_with(Vec::new);
             for family in &[4, 6] {
                 if let Some(reply) = self.read_reply()? {
-                    for elem in reply.split_whitespace() {
-                        let prefix = parse_prefix(elem)?;
-                        if family == &4 {
-                            assert!(prefix.0.is_ipv4());
-                        } else {
-                            assert!(prefix.0.is_ipv6());
-                        }
-                        prefixlist.push(prefix);
-                    }
+                    prefixlist.extend(parse_autnum_reply(&reply, *family)?);
                 }
             }
         }
 
         Ok(ret)
     }
+
+    /// Like `resolve_autnums`, but queries each source database separately so
+    /// that every prefix comes back as a route object carrying its source.
+    /// A prefix registered in several sources yields one object per source.
+    pub fn resolve_autnum_routes(
+        &mut self,
+        autnums: &Set<u32>,
+    ) -> AppResult<Map<u32, Vec<RouteObject>>> {
+        let sources: Vec<String> = self.sources.split(',').map(str::to_string).collect();
+        for source in sources.iter() {
+            writeln!(self.stream, "!s{}", source)?;
+            for autnum in autnums.iter() {
+                writeln!(self.stream, "!gas{}", autnum)?;
+                writeln!(self.stream, "!6as{}", autnum)?;
+            }
+        }
+        writeln!(self.stream, "!s{}", self.sources)?;
+        self.stream.flush()?;
+
+        let mut ret: Map<u32, Vec<RouteObject>> = Map::new();
+        for source in sources.iter() {
+            for autnum in autnums.iter() {
+                let routes = ret.entry(*autnum).or_default();
+                for family in &[4, 6] {
+                    if let Some(reply) = self.read_reply()? {
+                        routes.extend(parse_autnum_reply(&reply, *family)?.into_iter().map(
+                            |prefix| RouteObject {
+                                prefix,
+                                origin: *autnum,
+                                source: source.clone(),
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
 }
 
 impl Drop for IrrClient {
@@ -197,6 +261,279 @@ impl Drop for IrrClient {
     }
 }
 
+/// Pool of IRR clients.
+///
+/// Queries are sharded across several connections to the same server and the
+/// results merged, so one slow reply only holds up the queries pipelined
+/// behind it on the same connection.
+pub struct IrrPool {
+    clients: Vec<IrrClient>,
+}
+
+impl IrrPool {
+    pub fn open<S: ToSocketAddrs>(target: S, sources: &str, size: usize) -> AppResult<Self> {
+        let sock_addrs: Vec<SocketAddr> = target.to_socket_addrs()?.collect();
+        let clients = (0..size.max(1))
+            .map(|_| IrrClient::open(&sock_addrs[..], sources))
+            .collect::<AppResult<_>>()?;
+        Ok(IrrPool { clients })
+    }
+
+    pub fn size(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.clients[0].peer_addr()
+    }
+
+    /// Distributes `queries` round-robin over the connections in sorted order,
+    /// so the sharding is the same from run to run.
+    fn sharded<T, K, V, F>(&mut self, queries: &Set<T>, resolve: F) -> AppResult<Map<K, V>>
+    where
+        T: Copy + Ord + Hash + Send + Sync,
+        K: Eq + Hash + Send,
+        V: Send,
+        F: Fn(&mut IrrClient, &Set<T>) -> AppResult<Map<K, V>> + Sync,
+    {
+        let mut sorted: Vec<T> = queries.iter().copied().collect();
+        sorted.sort_unstable();
+        let mut shards: Vec<Set<T>> = self.clients.iter().map(|_| Set::new()).collect();
+        for (i, query) in sorted.into_iter().enumerate() {
+            shards[i % self.clients.len()].insert(query);
+        }
+        let resolve = &resolve;
+        thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .clients
+                .iter_mut()
+                .zip(shards.iter())
+                .filter(|(_, shard)| !shard.is_empty())
+                .map(|(client, shard)| scope.spawn(move || resolve(client, shard)))
+                .collect();
+            let mut ret = Map::new();
+            for worker in workers {
+                let shard_result = worker
+                    .join()
+                    .map_err(|_| Error::new(Other, "IRR worker thread panicked"))?;
+                ret.extend(shard_result?);
+            }
+            Ok(ret)
+        })
+    }
+
+    pub fn resolve_as_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<u32>>> {
+        self.sharded(sets, |client, shard| client.resolve_as_sets(shard))
+    }
+
+    pub fn resolve_set_members<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<String>>> {
+        self.sharded(sets, |client, shard| client.resolve_set_members(shard))
+    }
+
+    pub fn resolve_route_objects(
+        &mut self,
+        prefixes: &Set<Prefix>,
+    ) -> AppResult<Map<Prefix, Vec<RouteObject>>> {
+        self.sharded(prefixes, |client, shard| {
+            client.resolve_route_objects(shard)
+        })
+    }
+
+    pub fn resolve_route_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<RouteSetMember>>> {
+        self.sharded(sets, |client, shard| client.resolve_route_sets(shard))
+    }
+
+    pub fn resolve_objects<'a>(
+        &mut self,
+        class: &str,
+        keys: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        self.sharded(keys, |client, shard| client.resolve_objects(class, shard))
+    }
+
+    pub fn resolve_filter_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        self.sharded(sets, |client, shard| client.resolve_filter_sets(shard))
+    }
+
+    pub fn resolve_autnums(&mut self, autnums: &Set<u32>) -> AppResult<Map<u32, Vec<Prefix>>> {
+        self.sharded(autnums, |client, shard| client.resolve_autnums(shard))
+    }
+
+    pub fn resolve_autnum_routes(
+        &mut self,
+        autnums: &Set<u32>,
+    ) -> AppResult<Map<u32, Vec<RouteObject>>> {
+        self.sharded(autnums, |client, shard| client.resolve_autnum_routes(shard))
+    }
+}
+
+/// A `route` or `route6` object: a prefix together with its origin AS and
+/// the IRR source it was registered in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RouteObject {
+    pub prefix: Prefix,
+    pub origin: u32,
+    /// IRR database the object was found in
+    pub source: String,
+}
+
+/// Parses the RPSL text of one or more route objects, separated by blank lines.
+pub fn parse_route_objects(text: &str) -> AppResult<Vec<RouteObject>> {
+    let mut objects = Vec::new();
+    for object in text.split("\n\n").filter(|o| !o.trim().is_empty()) {
+        let (mut prefix, mut origin, mut source) = (None, None, None);
+        for line in object.lines() {
+            let mut attr = line.splitn(2, ':');
+            match (attr.next(), attr.next().map(str::trim)) {
+                (Some("route"), Some(value)) | (Some("route6"), Some(value)) => {
+                    prefix = Some(parse_prefix(value)?)
+                }
+                (Some("origin"), Some(value)) => origin = Some(parse_autnum(value)?),
+                (Some("source"), Some(value)) => source = Some(value.to_string()),
+                _ => continue,
+            }
+        }
+        match (prefix, origin, source) {
+            (Some(prefix), Some(origin), Some(source)) => objects.push(RouteObject {
+                prefix,
+                origin,
+                source,
+            }),
+            _ => return Err(Error::new(InvalidData, "incomplete route object").into()),
+        }
+    }
+    Ok(objects)
+}
+
+/// Attributes of an RPSL object, with continuation lines joined and comments
+/// removed.
+pub fn parse_attributes(object: &str) -> Vec<(&str, String)> {
+    let mut attributes: Vec<(&str, String)> = Vec::new();
+    for line in object.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        match (line.chars().next(), attributes.last_mut()) {
+            (Some(' ') | Some('\t') | Some('+'), Some((_, value))) => {
+                value.push(' ');
+                value.push_str(line[1..].trim());
+            }
+            _ => {
+                let mut attr = line.splitn(2, ':');
+                if let (Some(name), Some(value)) = (attr.next(), attr.next()) {
+                    attributes.push((name, value.trim().to_string()));
+                }
+            }
+        }
+    }
+    attributes
+}
+
+/// The filter expression of a filter-set object; `mp-filter` takes precedence
+/// over `filter`.
+pub(crate) fn parse_filter_set(text: &str) -> AppResult<String> {
+    let object = text.split("\n\n").next().unwrap_or("");
+    let attributes = parse_attributes(object);
+    ["mp-filter", "filter"]
+        .iter()
+        .find_map(|wanted| {
+            attributes
+                .iter()
+                .find(|(name, _)| name == wanted)
+                .map(|(_, value)| value.clone())
+        })
+        .ok_or_else(|| Error::new(InvalidData, "filter-set without filter").into())
+}
+
+/// Status line of an irrd reply.
+pub(crate) enum Status {
+    /// successful query returning data of the given length
+    Data(usize),
+    /// successful query returning no data
+    Done,
+    /// unsuccessful query - Key not found
+    NotFound,
+}
+
+pub(crate) fn parse_status(line: &[u8]) -> AppResult<Status> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    match line.split_first() {
+        Some((b'A', len_bytes)) => {
+            let content_len: usize = std::str::from_utf8(len_bytes)
+                .map_err(|e| Error::new(InvalidData, e))
+                .and_then(|s| s.parse().map_err(|e| Error::new(InvalidData, e)))?;
+            Ok(Status::Data(content_len))
+        }
+        Some((b'C', &[])) => Ok(Status::Done),
+        Some((b'D', &[])) => Ok(Status::NotFound),
+        // unsuccessful query - There are multiple copies of the key in one database
+        Some((b'E', &[])) => Err(Error::new(
+            Other,
+            "There are multiple copies of the key in one database",
+        )
+        .into()),
+        // other error
+        Some((b'F', data)) => Err(Error::new(Other, String::from_utf8_lossy(data)).into()),
+        Some((code, data)) => Err(Error::new(
+            InvalidData,
+            format!(
+                "invalid reply: {:?} => {:?}",
+                char::from(*code),
+                String::from_utf8_lossy(data)
+            ),
+        )
+        .into()),
+        None => Err(Error::new(Other, "short reply").into()),
+    }
+}
+
+/// Parses the members of a recursively expanded as-set (`!i{set},1`).
+///
+/// All members are returned as-is; see [`crate::asn::AsnPolicy`] for removing
+/// reserved or unwanted AS numbers.
+pub(crate) fn parse_as_set_reply(reply: &str) -> AppResult<Vec<u32>> {
+    reply
+        .split_whitespace()
+        .map(|elem| parse_autnum(elem).map_err(Into::into))
+        .collect()
+}
+
+/// Parses the prefixes of a recursively expanded route-set (`!i{set},1`).
+///
+/// Members the server didn't resolve, such as AS numbers or nested sets, are
+/// returned as they are.
+pub(crate) fn parse_route_set_reply(reply: &str) -> AppResult<Vec<RouteSetMember>> {
+    reply
+        .split_whitespace()
+        .map(RouteSetMember::parse)
+        .collect()
+}
+
+/// Parses the prefixes originated by an AS (`!gas{n}` or `!6as{n}`).
+pub(crate) fn parse_autnum_reply(reply: &str, family: u8) -> AppResult<Vec<Prefix>> {
+    let mut prefixes = Vec::new();
+    for elem in reply.split_whitespace() {
+        let prefix = parse_prefix(elem)?;
+        if family == 4 {
+            assert!(prefix.0.is_ipv4());
+        } else {
+            assert!(prefix.0.is_ipv6());
+        }
+        prefixes.push(prefix);
+    }
+    Ok(prefixes)
+}
+
 pub fn parse_autnum(input: &str) -> io::Result<u32> {
     if input.starts_with("AS") || input.starts_with("as") {
         input[2..].parse().map_err(|e| Error::new(InvalidData, e))
@@ -208,9 +545,141 @@ pub fn parse_autnum(input: &str) -> io::Result<u32> {
 pub fn parse_prefix(input: &str) -> io::Result<Prefix> {
     let mut elems = input.split('/');
     if let (Some(ip), Some(masklen), None) = (elems.next(), elems.next(), elems.next()) {
-        if let (Ok(ip), Ok(masklen)) = (ip.parse(), masklen.parse()) {
-            return Ok((ip, masklen));
+        if let (Ok(ip), Ok(masklen)) = (ip.parse::<IpAddr>(), masklen.parse::<u8>()) {
+            let width = if ip.is_ipv4() { 32 } else { 128 };
+            if masklen <= width {
+                return Ok((ip, masklen));
+            }
         }
     }
     Err(Error::new(InvalidData, input))
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+
+    /// Minimal irrd stand-in answering from a fixed table of `query -> reply`.
+    pub(crate) fn mock_server(table: &'static [(&'static str, &'static str)]) -> SocketAddr {
+        mock_server_with_delay(table, Duration::from_secs(0))
+    }
+
+    /// Like `mock_server`, but waits `delay` before answering each query.
+    pub(crate) fn mock_server_with_delay(
+        table: &'static [(&'static str, &'static str)],
+        delay: Duration,
+    ) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let conn = conn.unwrap();
+                thread::spawn(move || {
+                    let mut writer = conn.try_clone().unwrap();
+                    for line in BufReader::new(conn).lines() {
+                        let line = line.unwrap();
+                        let reply = match line.as_str() {
+                            "!!" => continue,
+                            "!q" => break,
+                            q if q.starts_with("!n") || q.starts_with("!s") => "C\n".to_string(),
+                            q => {
+                                thread::sleep(delay);
+                                match table.iter().find(|(k, _)| *k == q) {
+                                    Some((_, v)) => format!("A{}\n{}\nC\n", v.len() + 1, v),
+                                    None => "D\n".to_string(),
+                                }
+                            }
+                        };
+                        writer.write_all(reply.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    const TABLE: &[(&str, &str)] = &[
+        ("!iAS-FOO,1", "AS64500 AS65001 AS3333"),
+        ("!iAS-BAR,1", "AS2914"),
+        ("!iRS-FOO,1", "192.0.2.0/24 2001:db8::/32"),
+        ("!gas3333", "193.0.0.0/21"),
+        ("!6as3333", "2001:67c:2e8::/48"),
+        ("!gas2914", "198.51.100.0/24 203.0.113.0/24"),
+    ];
+
+    #[test]
+    fn pool_matches_single_client() {
+        let addr = mock_server(TABLE);
+        let sets: Set<&str> = ["AS-FOO", "AS-BAR", "AS-MISSING"].iter().copied().collect();
+        let autnums: Set<u32> = [3333, 2914, 1].iter().copied().collect();
+
+        let mut client = IrrClient::open(addr, "RADB").unwrap();
+        let mut pool = IrrPool::open(addr, "RADB", 3).unwrap();
+        assert_eq!(pool.size(), 3);
+
+        let members = pool.resolve_as_sets(&sets).unwrap();
+        assert_eq!(members, client.resolve_as_sets(&sets).unwrap());
+        // reserved AS numbers are left to the caller's AsnPolicy
+        assert_eq!(members["AS-FOO"], vec![64500, 65001, 3333]);
+        assert!(members["AS-MISSING"].is_empty());
+
+        let route_sets: Set<&str> = ["RS-FOO"].iter().copied().collect();
+        assert_eq!(
+            pool.resolve_route_sets(&route_sets).unwrap(),
+            client.resolve_route_sets(&route_sets).unwrap()
+        );
+
+        let prefixes = pool.resolve_autnums(&autnums).unwrap();
+        assert_eq!(prefixes, client.resolve_autnums(&autnums).unwrap());
+        assert_eq!(prefixes[&2914].len(), 2);
+        assert_eq!(prefixes[&3333].len(), 2);
+    }
+
+    #[test]
+    fn routes_carry_source_and_origin() {
+        let addr = mock_server(TABLE);
+        let autnums: Set<u32> = [2914].iter().copied().collect();
+        let mut client = IrrClient::open(addr, "RIPE,RADB").unwrap();
+        let mut routes = client.resolve_autnum_routes(&autnums).unwrap()[&2914].clone();
+        routes.sort_unstable();
+        assert_eq!(routes.len(), 4);
+        assert_eq!(
+            routes[0],
+            RouteObject {
+                prefix: parse_prefix("198.51.100.0/24").unwrap(),
+                origin: 2914,
+                source: "RADB".to_string(),
+            }
+        );
+        // the full source list is selected again afterwards
+        assert_eq!(client.resolve_autnums(&autnums).unwrap()[&2914].len(), 2);
+    }
+
+    #[test]
+    fn parses_filter_sets() {
+        let object = "filter-set:  FLTR-FOO\n\
+                      filter:      AS64500 # customer\n\
+                      mp-filter:   AS64500\n\
+                      \x20            OR {2001:db8::/32^+}\n\
+                      +            OR AS-FOO\n\
+                      source:      RADB\n";
+        assert_eq!(
+            parse_filter_set(object).unwrap(),
+            "AS64500 OR {2001:db8::/32^+} OR AS-FOO"
+        );
+        assert!(parse_filter_set("filter-set: FLTR-FOO\nsource: RADB\n").is_err());
+    }
+
+    #[test]
+    fn rejects_long_masks() {
+        assert!(parse_prefix("10.0.0.0/32").is_ok());
+        assert!(parse_prefix("2001:db8::/128").is_ok());
+        assert!(parse_prefix("10.0.0.0/33").is_err());
+        assert!(parse_prefix("2001:db8::/129").is_err());
+        assert!(parse_prefix("10.0.0.0/").is_err());
+    }
+}
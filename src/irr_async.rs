@@ -0,0 +1,250 @@
+use std::{
+    future::Future,
+    io::{Error, ErrorKind::*},
+    net::SocketAddr,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::{TcpStream, ToSocketAddrs},
+    time::timeout,
+};
+
+use crate::{
+    irr::{parse_as_set_reply, parse_autnum_reply, parse_route_set_reply, parse_status, Status},
+    rpsl::RouteSetMember,
+    AppResult, Map, Prefix, Set,
+};
+
+/// Async IRR client.
+///
+/// Speaks the same pipelined protocol as [`IrrClient`](crate::irr::IrrClient).
+/// Every call is bounded by the client timeout. A call that is cancelled or
+/// times out between replies leaves them unread on the stream; they are
+/// drained before the next call is sent. A call cancelled halfway through
+/// writing queries or reading a reply leaves the stream unusable, and later
+/// calls fail.
+pub struct AsyncIrrClient {
+    stream: BufStream<TcpStream>,
+    buf: Vec<u8>,
+    timeout: Duration,
+    /// replies still owed by the server for queries already sent
+    outstanding: usize,
+    /// set while the stream is in the middle of a write or a reply
+    desynced: bool,
+}
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+async fn with_timeout<T, F>(duration: Duration, fut: F) -> AppResult<T>
+where
+    F: Future<Output = AppResult<T>>,
+{
+    timeout(duration, fut)
+        .await
+        .map_err(|_| Error::new(TimedOut, "IRR query timed out"))?
+}
+
+impl AsyncIrrClient {
+    pub async fn open<S: ToSocketAddrs>(target: S, sources: &str) -> AppResult<Self> {
+        let conn = with_timeout(TIMEOUT, async {
+            TcpStream::connect(target).await.map_err(Into::into)
+        })
+        .await?;
+        let mut client = AsyncIrrClient {
+            stream: BufStream::new(conn),
+            buf: Vec::with_capacity(4096),
+            timeout: TIMEOUT,
+            outstanding: 0,
+            desynced: false,
+        };
+        let hello = format!(
+            "!!\n!n{}-{}\n!s{}\n",
+            crate::CLIENT,
+            crate::VERSION,
+            sources
+        );
+        client.stream.write_all(hello.as_bytes()).await?;
+        Ok(client)
+    }
+
+    /// Sets the time limit for each resolve call (default 30 s).
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub async fn close(&mut self) -> AppResult<()> {
+        self.stream.write_all(b"!q\n").await?;
+        self.stream.flush().await?;
+        self.stream.get_mut().shutdown().await?;
+        Ok(())
+    }
+
+    pub fn peer_addr(&self) -> AppResult<SocketAddr> {
+        Ok(self.stream.get_ref().peer_addr()?)
+    }
+
+    async fn read_reply(&mut self) -> AppResult<Option<String>> {
+        let mut reply: Option<String> = None;
+        loop {
+            // wait for the reply without consuming it, so that a call
+            // cancelled here leaves the stream intact
+            self.stream.fill_buf().await?;
+            self.desynced = true;
+            self.buf.clear();
+            self.stream.read_until(b'\n', &mut self.buf).await?;
+            match parse_status(&self.buf)? {
+                Status::Data(content_len) => {
+                    self.buf.resize(content_len, 0);
+                    self.stream.read_exact(&mut self.buf).await?;
+                    let content = String::from_utf8(self.buf.clone())
+                        .map_err(|e| Error::new(InvalidData, e))?;
+                    reply = Some(content);
+                }
+                Status::Done => {
+                    if reply.is_some() {
+                        break;
+                    }
+                    // a stray completion line, nothing half-read
+                    self.desynced = false;
+                }
+                Status::NotFound => break,
+            }
+        }
+        self.outstanding -= 1;
+        self.desynced = false;
+        Ok(reply)
+    }
+
+    /// Drains replies left over from a cancelled call, then pipelines `queries`.
+    async fn send(&mut self, queries: String, count: usize) -> AppResult<()> {
+        if self.desynced {
+            return Err(Error::new(
+                BrokenPipe,
+                "IRR connection desynchronized by a cancelled query",
+            )
+            .into());
+        }
+        while self.outstanding > 0 {
+            self.read_reply().await?;
+        }
+        self.desynced = true;
+        self.stream.write_all(queries.as_bytes()).await?;
+        self.stream.flush().await?;
+        self.outstanding = count;
+        self.desynced = false;
+        Ok(())
+    }
+
+    pub async fn resolve_as_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<u32>>> {
+        with_timeout(self.timeout, async {
+            let queries: String = sets.iter().map(|set| format!("!i{},1\n", set)).collect();
+            self.send(queries, sets.len()).await?;
+            let mut ret: Map<&str, Vec<u32>> = Map::new();
+            for set in sets.iter() {
+                let autnums = ret.entry(set).or_default();
+                if let Some(reply) = self.read_reply().await? {
+                    autnums.extend(parse_as_set_reply(&reply)?);
+                }
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    pub async fn resolve_route_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<RouteSetMember>>> {
+        with_timeout(self.timeout, async {
+            let queries: String = sets.iter().map(|set| format!("!i{},1\n", set)).collect();
+            self.send(queries, sets.len()).await?;
+            let mut ret: Map<&str, Vec<RouteSetMember>> = Map::new();
+            for set in sets.iter() {
+                let prefixlist = ret.entry(*set).or_default();
+                if let Some(reply) = self.read_reply().await? {
+                    prefixlist.extend(parse_route_set_reply(&reply)?);
+                }
+            }
+            Ok(ret)
+        })
+        .await
+    }
+
+    pub async fn resolve_autnums(
+        &mut self,
+        autnums: &Set<u32>,
+    ) -> AppResult<Map<u32, Vec<Prefix>>> {
+        with_timeout(self.timeout, async {
+            let queries: String = autnums
+                .iter()
+                .map(|autnum| format!("!gas{0}\n!6as{0}\n", autnum))
+                .collect();
+            self.send(queries, autnums.len() * 2).await?;
+            let mut ret = Map::new();
+            for autnum in autnums.iter() {
+                let prefixlist: &mut Vec<Prefix> = ret.entry(*autnum).or_default();
+                for family in &[4, 6] {
+                    if let Some(reply) = self.read_reply().await? {
+                        prefixlist.extend(parse_autnum_reply(&reply, *family)?);
+                    }
+                }
+            }
+            Ok(ret)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irr::{
+        tests::{mock_server, mock_server_with_delay},
+        IrrClient,
+    };
+
+    const TABLE: &[(&str, &str)] = &[
+        ("!iAS-FOO,1", "AS64500 AS3333"),
+        ("!gas3333", "193.0.0.0/21"),
+        ("!6as3333", "2001:67c:2e8::/48"),
+    ];
+
+    #[tokio::test]
+    async fn same_replies_as_blocking_client() {
+        let addr = mock_server(TABLE);
+        let sets: Set<&str> = ["AS-FOO", "AS-MISSING"].iter().copied().collect();
+        let autnums: Set<u32> = [3333, 1].iter().copied().collect();
+
+        let mut client = AsyncIrrClient::open(addr, "RADB").await.unwrap();
+        let blocking_sets = IrrClient::open(addr, "RADB")
+            .unwrap()
+            .resolve_as_sets(&sets)
+            .unwrap();
+        assert_eq!(client.resolve_as_sets(&sets).await.unwrap(), blocking_sets);
+        let prefixes = client.resolve_autnums(&autnums).await.unwrap();
+        assert_eq!(prefixes[&3333].len(), 2);
+        assert!(prefixes[&1].is_empty());
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recovers_after_cancellation() {
+        let addr = mock_server_with_delay(TABLE, Duration::from_millis(100));
+        let autnums: Set<u32> = [3333].iter().copied().collect();
+        let mut client = AsyncIrrClient::open(addr, "RADB").await.unwrap();
+
+        // the queries are sent, but the call times out before any reply is read
+        client.set_timeout(Duration::from_millis(20));
+        assert!(client.resolve_autnums(&autnums).await.is_err());
+        assert_eq!(client.outstanding, 2);
+        client.set_timeout(TIMEOUT);
+
+        let prefixes = client.resolve_autnums(&autnums).await.unwrap();
+        assert_eq!(prefixes[&3333].len(), 2);
+    }
+}
@@ -0,0 +1,485 @@
+//! IRR data recorded from the servers during a run, and replayed from
+//! snapshots of earlier runs.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{create_dir_all, rename, File},
+    io::{BufReader, BufWriter, Write},
+    net::SocketAddr,
+};
+
+use crate::{
+    irr::parse_prefix, rpsl::RouteSetMember, servers::IrrServers, AppResult, Map, Prefix,
+    RouteObject, Set,
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// Version of the IRR snapshot format.
+const FORMAT: u32 = 1;
+
+/// The IRR data one list of servers and sources answered with in a run, as
+/// used to build filters: as-set members, route-sets, filter-sets, aut-num
+/// objects and the prefixes of each AS number.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IrrData {
+    pub servers: Vec<String>,
+    pub sources: Vec<String>,
+    /// the servers that answered each kind of query
+    pub answers: Vec<(String, Vec<String>)>,
+    /// objects by class and key, e.g. aut-num objects of export policies
+    pub objects: BTreeMap<String, BTreeMap<String, String>>,
+    pub filter_sets: BTreeMap<String, String>,
+    pub route_sets: BTreeMap<String, Vec<String>>,
+    /// as-sets expanded by the server
+    pub as_sets: BTreeMap<String, Vec<u32>>,
+    /// direct members of as-sets expanded by fup
+    pub set_members: BTreeMap<String, Vec<String>>,
+    pub autnums: BTreeMap<u32, Vec<String>>,
+    /// prefix and source of each route object, with `provenance`
+    pub autnum_routes: BTreeMap<u32, Vec<(String, String)>>,
+    /// keys queried that the servers had nothing for, by kind of query
+    #[serde(default)]
+    pub missing: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl IrrData {
+    pub fn new(servers: &[String], sources: &[String]) -> Self {
+        IrrData {
+            servers: servers.to_vec(),
+            sources: sources.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Records the `keys` of a `kind` of query that got no answer.
+    fn record_missing(&mut self, kind: &str, keys: impl Iterator<Item = String>) {
+        let keys: BTreeSet<String> = keys.collect();
+        if !keys.is_empty() {
+            self.missing
+                .entry(kind.to_string())
+                .or_default()
+                .extend(keys);
+        }
+    }
+
+    /// Whether `key` got no answer when recorded, or else was never queried.
+    fn check_missing(&self, kind: &str, key: &str) -> AppResult<()> {
+        match self.missing.get(kind) {
+            Some(missing) if missing.contains(key) => Ok(()),
+            _ => Err(format!("no {} {} in the IRR snapshot", kind, key).into()),
+        }
+    }
+}
+
+/// The IRR data of a whole run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IrrSnapshot {
+    pub format: u32,
+    /// client name and version
+    pub client: String,
+    pub time: String,
+    pub databases: Vec<IrrData>,
+}
+
+impl IrrSnapshot {
+    pub fn new(time: String, databases: Vec<IrrData>) -> Self {
+        IrrSnapshot {
+            format: FORMAT,
+            client: format!("{}-{}", crate::CLIENT, crate::VERSION),
+            time,
+            databases,
+        }
+    }
+
+    pub fn load(path: &str) -> AppResult<Self> {
+        let file = File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+        let snapshot: IrrSnapshot = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| format!("failed to parse {}: {}", path, e))?;
+        if snapshot.format != FORMAT {
+            return Err(
+                format!("{}: unsupported snapshot format {}", path, snapshot.format).into(),
+            );
+        }
+        Ok(snapshot)
+    }
+
+    /// Saves the snapshot in `dir` under a name made from `stamp`, returning
+    /// its path.
+    pub fn save(&self, dir: &str, stamp: &str) -> AppResult<String> {
+        create_dir_all(dir).map_err(|e| format!("failed to create {}: {}", dir, e))?;
+        let path = format!("{}/irr-{}.json", dir, stamp);
+        let temp_path = format!("{}.tmp", path);
+        let file = File::create(&temp_path)
+            .map_err(|e| format!("failed to create {}: {}", temp_path, e))?;
+        let mut writer = BufWriter::new(file);
+        // one value per line, so that snapshots diff well
+        serde_json::to_writer_pretty(&mut writer, self)
+            .map_err(|e| format!("failed to write {}: {}", temp_path, e))?;
+        writer
+            .flush()
+            .map_err(|e| format!("failed to write {}: {}", temp_path, e))?;
+        rename(&temp_path, &path)
+            .map_err(|e| format!("rename {} to {}: {}", temp_path, path, e))?;
+        Ok(path)
+    }
+
+    /// The data of `servers` and `sources`, or else of the same sources at
+    /// other servers.
+    pub fn database(&self, servers: &[String], sources: &[String]) -> AppResult<&IrrData> {
+        self.databases
+            .iter()
+            .find(|data| data.servers == servers && data.sources == sources)
+            .or_else(|| self.databases.iter().find(|data| data.sources == sources))
+            .ok_or_else(|| format!("no data for sources {} in snapshot", sources.join(",")).into())
+    }
+}
+
+/// Where filters get their IRR data: the servers, recording what they
+/// answer, or a snapshot of an earlier run.
+pub enum Irr<'s> {
+    Live(IrrServers, Box<IrrData>),
+    Replay(&'s IrrData),
+}
+
+fn prefix_string(prefix: &Prefix) -> String {
+    format!("{}/{}", prefix.0, prefix.1)
+}
+
+/// Values of `recorded` for `keys`, each of which must be recorded in `data`
+/// as answered or missing for the `kind` of query.
+fn replay<'a, V, T>(
+    data: &IrrData,
+    kind: &str,
+    keys: &Set<&'a str>,
+    recorded: Option<&BTreeMap<String, V>>,
+    convert: impl Fn(&V) -> AppResult<T>,
+) -> AppResult<Map<&'a str, T>> {
+    let mut ret = Map::new();
+    for key in keys {
+        match recorded.and_then(|recorded| recorded.get(*key)) {
+            Some(value) => {
+                let value = convert(value).map_err(|e| format!("{}: {}", key, e))?;
+                ret.insert(*key, value);
+            }
+            None => data.check_missing(kind, key)?,
+        }
+    }
+    Ok(ret)
+}
+
+impl Irr<'_> {
+    /// The servers and sources the data comes from.
+    pub fn database(&self) -> (&[String], &[String]) {
+        let data: &IrrData = match self {
+            Irr::Live(_, data) => data,
+            Irr::Replay(data) => data,
+        };
+        (&data.servers, &data.sources)
+    }
+
+    /// Each server connected to, with its address and sources.
+    pub fn peers(&self) -> Vec<(&str, SocketAddr, &str)> {
+        match self {
+            Irr::Live(client, _) => client.peers(),
+            Irr::Replay(_) => Vec::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Irr::Live(client, _) => client.size(),
+            Irr::Replay(_) => 0,
+        }
+    }
+
+    pub fn answers(&self) -> &[(String, Vec<String>)] {
+        match self {
+            Irr::Live(client, _) => client.answers(),
+            Irr::Replay(data) => &data.answers,
+        }
+    }
+
+    pub fn failures(&self) -> &[(String, String)] {
+        match self {
+            Irr::Live(client, _) => client.failures(),
+            Irr::Replay(_) => &[],
+        }
+    }
+
+    /// The data recorded so far, none when replaying.
+    pub fn into_data(self) -> Option<IrrData> {
+        match self {
+            Irr::Live(client, mut data) => {
+                data.answers = client.answers().to_vec();
+                Some(*data)
+            }
+            Irr::Replay(_) => None,
+        }
+    }
+
+    pub fn resolve_objects<'a>(
+        &mut self,
+        class: &str,
+        keys: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        match self {
+            Irr::Live(client, data) => {
+                let objects = client.resolve_objects(class, keys)?;
+                let recorded = data.objects.entry(class.to_string()).or_default();
+                for (key, object) in objects.iter() {
+                    recorded.insert(key.to_string(), object.clone());
+                }
+                let missing = keys.iter().filter(|key| !objects.contains_key(*key));
+                data.record_missing(class, missing.map(|key| key.to_string()));
+                Ok(objects)
+            }
+            Irr::Replay(data) => replay(data, class, keys, data.objects.get(class), |object| {
+                Ok(object.clone())
+            }),
+        }
+    }
+
+    pub fn resolve_filter_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        match self {
+            Irr::Live(client, data) => {
+                let filter_sets = client.resolve_filter_sets(sets)?;
+                for (set, filter) in filter_sets.iter() {
+                    data.filter_sets.insert(set.to_string(), filter.clone());
+                }
+                let missing = sets.iter().filter(|set| !filter_sets.contains_key(*set));
+                data.record_missing("filter-set", missing.map(|set| set.to_string()));
+                Ok(filter_sets)
+            }
+            Irr::Replay(data) => replay(
+                data,
+                "filter-set",
+                sets,
+                Some(&data.filter_sets),
+                |filter| Ok(filter.clone()),
+            ),
+        }
+    }
+
+    pub fn resolve_route_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<RouteSetMember>>> {
+        match self {
+            Irr::Live(client, data) => {
+                let route_sets = client.resolve_route_sets(sets)?;
+                for (set, members) in route_sets.iter() {
+                    let members = members.iter().map(ToString::to_string).collect();
+                    data.route_sets.insert(set.to_string(), members);
+                }
+                let missing = sets.iter().filter(|set| !route_sets.contains_key(*set));
+                data.record_missing("route-set", missing.map(|set| set.to_string()));
+                Ok(route_sets)
+            }
+            Irr::Replay(data) => {
+                replay(data, "route-set", sets, Some(&data.route_sets), |members| {
+                    members
+                        .iter()
+                        .map(|member| RouteSetMember::parse(member))
+                        .collect()
+                })
+            }
+        }
+    }
+
+    pub fn resolve_as_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<u32>>> {
+        match self {
+            Irr::Live(client, data) => {
+                let as_sets = client.resolve_as_sets(sets)?;
+                for (set, members) in as_sets.iter() {
+                    data.as_sets.insert(set.to_string(), members.clone());
+                }
+                let missing = sets.iter().filter(|set| !as_sets.contains_key(*set));
+                data.record_missing("as-set", missing.map(|set| set.to_string()));
+                Ok(as_sets)
+            }
+            Irr::Replay(data) => replay(data, "as-set", sets, Some(&data.as_sets), |members| {
+                Ok(members.clone())
+            }),
+        }
+    }
+
+    pub fn resolve_set_members<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<String>>> {
+        match self {
+            Irr::Live(client, data) => {
+                let set_members = client.resolve_set_members(sets)?;
+                for (set, members) in set_members.iter() {
+                    data.set_members.insert(set.to_string(), members.clone());
+                }
+                let missing = sets.iter().filter(|set| !set_members.contains_key(*set));
+                data.record_missing("as-set members", missing.map(|set| set.to_string()));
+                Ok(set_members)
+            }
+            Irr::Replay(data) => replay(
+                data,
+                "as-set members",
+                sets,
+                Some(&data.set_members),
+                |members| Ok(members.clone()),
+            ),
+        }
+    }
+
+    pub fn resolve_autnums(&mut self, autnums: &Set<u32>) -> AppResult<Map<u32, Vec<Prefix>>> {
+        match self {
+            Irr::Live(client, data) => {
+                let prefixes = client.resolve_autnums(autnums)?;
+                for (num, prefixes) in prefixes.iter() {
+                    let prefixes = prefixes.iter().map(prefix_string).collect();
+                    data.autnums.insert(*num, prefixes);
+                }
+                let missing = autnums.iter().filter(|num| !prefixes.contains_key(*num));
+                data.record_missing("routes of", missing.map(|num| format!("AS{}", num)));
+                Ok(prefixes)
+            }
+            Irr::Replay(data) => {
+                let mut ret = Map::new();
+                for num in autnums {
+                    if let Some(prefixes) = data.autnums.get(num) {
+                        let prefixes = prefixes
+                            .iter()
+                            .map(|prefix| parse_prefix(prefix))
+                            .collect::<Result<_, _>>()
+                            .map_err(|e| format!("AS{}: {}", num, e))?;
+                        ret.insert(*num, prefixes);
+                    } else {
+                        data.check_missing("routes of", &format!("AS{}", num))?;
+                    }
+                }
+                Ok(ret)
+            }
+        }
+    }
+
+    pub fn resolve_autnum_routes(
+        &mut self,
+        autnums: &Set<u32>,
+    ) -> AppResult<Map<u32, Vec<RouteObject>>> {
+        match self {
+            Irr::Live(client, data) => {
+                let routes = client.resolve_autnum_routes(autnums)?;
+                for (num, routes) in routes.iter() {
+                    let routes = routes
+                        .iter()
+                        .map(|route| (prefix_string(&route.prefix), route.source.clone()))
+                        .collect();
+                    data.autnum_routes.insert(*num, routes);
+                }
+                let missing = autnums.iter().filter(|num| !routes.contains_key(*num));
+                data.record_missing("route objects of", missing.map(|num| format!("AS{}", num)));
+                Ok(routes)
+            }
+            Irr::Replay(data) => {
+                let mut ret = Map::new();
+                for num in autnums {
+                    if let Some(routes) = data.autnum_routes.get(num) {
+                        let routes = routes
+                            .iter()
+                            .map(|(prefix, source)| {
+                                Ok(RouteObject {
+                                    prefix: parse_prefix(prefix)?,
+                                    origin: *num,
+                                    source: source.clone(),
+                                })
+                            })
+                            .collect::<AppResult<_>>()
+                            .map_err(|e| format!("AS{}: {}", num, e))?;
+                        ret.insert(*num, routes);
+                    } else {
+                        data.check_missing("route objects of", &format!("AS{}", num))?;
+                    }
+                }
+                Ok(ret)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{irr::tests::mock_server, servers::Strategy};
+
+    const TABLE: &[(&str, &str)] = &[
+        ("!iAS-FOO,1", "AS64500 AS65001"),
+        (
+            "!iRS-FOO,1",
+            "192.0.2.0/24^+ AS64500^24-32 AS-FOO 2001:db8::/32^48",
+        ),
+        (
+            "!maut-num,AS64500",
+            "aut-num: AS64500\nas-name: FOO\nsource: RADB",
+        ),
+        (
+            "!mfilter-set,FLTR-FOO",
+            "filter-set: FLTR-FOO\nfilter: AS-FOO AND NOT {0.0.0.0/0^+}\nsource: RADB",
+        ),
+        ("!gas64500", "192.0.2.0/24 198.51.100.0/24"),
+        ("!6as64500", "2001:db8::/32"),
+    ];
+
+    fn set<'a>(keys: &[&'a str]) -> Set<&'a str> {
+        keys.iter().copied().collect()
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let server = mock_server(TABLE).to_string();
+        let client = IrrServers::open(&[&server], "RADB", 1, Strategy::Failover).unwrap();
+        let data = IrrData::new(&[server], &["RADB".to_string()]);
+        let mut live = Irr::Live(client, Box::new(data));
+        let autnums = [64500, 65001].iter().copied().collect();
+        let objects = live
+            .resolve_objects("aut-num", &set(&["AS64500", "AS65001"]))
+            .unwrap();
+        let filter_sets = live.resolve_filter_sets(&set(&["FLTR-FOO"])).unwrap();
+        let route_sets = live.resolve_route_sets(&set(&["RS-FOO"])).unwrap();
+        let as_sets = live.resolve_as_sets(&set(&["AS-FOO"])).unwrap();
+        let prefixes = live.resolve_autnums(&autnums).unwrap();
+        let routes = live.resolve_autnum_routes(&autnums).unwrap();
+        assert_eq!(route_sets["RS-FOO"].len(), 4);
+        assert_eq!(prefixes[&64500].len(), 3);
+
+        // through JSON, as saved
+        let data = live.into_data().unwrap();
+        let data: IrrData = serde_json::from_str(&serde_json::to_string(&data).unwrap()).unwrap();
+        assert_eq!(data.missing["aut-num"].len(), 1);
+        let mut replay = Irr::Replay(&data);
+        assert_eq!(
+            replay
+                .resolve_objects("aut-num", &set(&["AS64500", "AS65001"]))
+                .unwrap(),
+            objects
+        );
+        assert_eq!(
+            replay.resolve_filter_sets(&set(&["FLTR-FOO"])).unwrap(),
+            filter_sets
+        );
+        assert_eq!(
+            replay.resolve_route_sets(&set(&["RS-FOO"])).unwrap(),
+            route_sets
+        );
+        assert_eq!(replay.resolve_as_sets(&set(&["AS-FOO"])).unwrap(), as_sets);
+        assert_eq!(replay.resolve_autnums(&autnums).unwrap(), prefixes);
+        assert_eq!(replay.resolve_autnum_routes(&autnums).unwrap(), routes);
+
+        // never queried
+        let e = replay.resolve_as_sets(&set(&["AS-BAR"])).unwrap_err();
+        assert_eq!(e.to_string(), "no as-set AS-BAR in the IRR snapshot");
+        let autnums = [64501].iter().copied().collect();
+        assert!(replay.resolve_autnums(&autnums).is_err());
+    }
+}
@@ -1,8 +1,21 @@
 #![forbid(unsafe_code)]
 pub mod aggregate;
+pub mod asn;
+pub mod bogon;
+pub mod deploy;
+pub mod expand;
+pub mod filter;
 pub mod filterclass;
 pub mod format;
 pub mod irr;
+#[cfg(feature = "async")]
+pub mod irr_async;
+pub mod irrdata;
+pub mod netconf;
+pub mod policy;
+pub mod rpki;
+pub mod rpsl;
+pub mod servers;
 
 pub(crate) use std::collections::{HashMap, HashSet};
 
@@ -10,7 +23,8 @@ pub type Map<K, V> = HashMap<K, V>;
 pub type Set<K> = HashSet<K>;
 
 pub type Prefix = (std::net::IpAddr, u8);
-pub type AppResult<T> = Result<T, Box<dyn std::error::Error>>;
+pub use irr::RouteObject;
+pub type AppResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 pub const CLIENT: &str = env!("CARGO_PKG_NAME");
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
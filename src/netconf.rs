@@ -0,0 +1,380 @@
+//! NETCONF client (RFC 6241) over an established connection, such as the
+//! input and output of `ssh -s router netconf`. It knows just enough XML to
+//! read replies.
+
+use std::io::{BufRead, Write};
+
+use crate::AppResult;
+
+pub const BASE_NS: &str = "urn:ietf:params:xml:ns:netconf:base:1.0";
+pub const BASE_10: &str = "urn:ietf:params:netconf:base:1.0";
+pub const BASE_11: &str = "urn:ietf:params:netconf:base:1.1";
+pub const CANDIDATE: &str = "urn:ietf:params:netconf:capability:candidate:1.0";
+/// confirmed commits of either version
+pub const CONFIRMED_COMMIT: &str = "urn:ietf:params:netconf:capability:confirmed-commit:";
+/// confirmed commits that can be cancelled
+pub const CONFIRMED_COMMIT_11: &str = "urn:ietf:params:netconf:capability:confirmed-commit:1.1";
+
+const END_OF_MESSAGE: &[u8] = b"]]>]]>";
+
+/// Reads a message framed with the end-of-message marker of NETCONF 1.0, or
+/// with the chunks of NETCONF 1.1.
+pub fn read_message<R: BufRead>(reader: &mut R, chunked: bool) -> AppResult<String> {
+    let mut message = Vec::new();
+    if !chunked {
+        while !message.ends_with(END_OF_MESSAGE) {
+            if reader.read_until(b'>', &mut message)? == 0 {
+                return Err("connection closed in the middle of a message".into());
+            }
+        }
+        message.truncate(message.len() - END_OF_MESSAGE.len());
+        return Ok(String::from_utf8(message)?);
+    }
+    loop {
+        // a chunk starts with "\n#<size>\n", the message ends with "\n##\n"
+        let mut header = Vec::new();
+        while header.is_empty() || header == b"\n" {
+            header.clear();
+            if reader.read_until(b'\n', &mut header)? == 0 {
+                return Err("connection closed in the middle of a message".into());
+            }
+        }
+        let header = String::from_utf8(header)?;
+        match header.trim_end().strip_prefix('#') {
+            Some("#") => break,
+            Some(size) => {
+                let size: usize = size
+                    .parse()
+                    .map_err(|_| format!("invalid chunk header {:?}", header))?;
+                let start = message.len();
+                message.resize(start + size, 0);
+                reader.read_exact(&mut message[start..])?;
+            }
+            None => return Err(format!("invalid chunk header {:?}", header).into()),
+        }
+    }
+    Ok(String::from_utf8(message)?)
+}
+
+pub fn write_message<W: Write>(writer: &mut W, chunked: bool, message: &str) -> AppResult<()> {
+    // in one write, as small writes can wait for acknowledgements
+    let framed = match chunked {
+        true => format!("\n#{}\n{}\n##\n", message.len(), message),
+        false => format!("{}]]>]]>", message),
+    };
+    writer.write_all(framed.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Contents of each `name` element in `xml`, with or without a namespace
+/// prefix. Elements of the same name must not nest.
+pub fn elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let tag_end = match rest.find('>') {
+            Some(tag_end) => tag_end,
+            None => break,
+        };
+        let tag = &rest[..tag_end];
+        let tag_name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if tag_name.is_empty() || tag_name.rsplit(':').next() != Some(name) {
+            continue;
+        }
+        rest = &rest[tag_end + 1..];
+        if tag.ends_with('/') {
+            found.push("");
+            continue;
+        }
+        let close = format!("</{}>", tag_name);
+        match rest.find(&close) {
+            Some(end) => {
+                found.push(&rest[..end]);
+                rest = &rest[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+/// `text` with the characters XML reserves escaped.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// A NETCONF session, past the exchange of hello messages.
+pub struct Session<R, W> {
+    reader: R,
+    writer: W,
+    /// whether both sides speak NETCONF 1.1
+    chunked: bool,
+    capabilities: Vec<String>,
+    message_id: u32,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    pub fn new(mut reader: R, mut writer: W) -> AppResult<Self> {
+        let hello = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <hello xmlns=\"{}\"><capabilities>\
+             <capability>{}</capability><capability>{}</capability>\
+             </capabilities></hello>",
+            BASE_NS, BASE_10, BASE_11
+        );
+        write_message(&mut writer, false, &hello)?;
+        let reply = read_message(&mut reader, false)?;
+        if elements(&reply, "hello").is_empty() {
+            return Err("no hello from the NETCONF server".into());
+        }
+        let capabilities: Vec<String> = elements(&reply, "capability")
+            .into_iter()
+            .map(|capability| unescape(capability.trim()))
+            .collect();
+        Ok(Session {
+            reader,
+            writer,
+            chunked: capabilities.iter().any(|capability| capability == BASE_11),
+            capabilities,
+            message_id: 0,
+        })
+    }
+
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    /// Whether the server announced a capability starting with `capability`,
+    /// e.g. `CONFIRMED_COMMIT`.
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|announced| announced.starts_with(capability))
+    }
+
+    /// Sends the `operation` element in an rpc and returns the reply, or the
+    /// errors in it.
+    pub fn rpc(&mut self, operation: &str) -> AppResult<String> {
+        self.message_id += 1;
+        let rpc = format!(
+            "<rpc xmlns=\"{}\" message-id=\"{}\">{}</rpc>",
+            BASE_NS, self.message_id, operation
+        );
+        write_message(&mut self.writer, self.chunked, &rpc)?;
+        let reply = read_message(&mut self.reader, self.chunked)?;
+        if elements(&reply, "rpc-reply").is_empty() {
+            return Err(format!("unexpected reply {}", reply.trim()).into());
+        }
+        let errors: Vec<String> = elements(&reply, "rpc-error")
+            .into_iter()
+            .filter(|error| elements(error, "error-severity").first() != Some(&"warning"))
+            .map(|error| {
+                let text = elements(error, "error-message")
+                    .into_iter()
+                    .chain(elements(error, "error-tag"))
+                    .next()
+                    .unwrap_or("unknown error");
+                unescape(text.trim())
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(reply),
+            false => Err(errors.join("; ").into()),
+        }
+    }
+
+    pub fn lock(&mut self, target: &str) -> AppResult<()> {
+        self.rpc(&format!("<lock><target><{}/></target></lock>", target))?;
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, target: &str) -> AppResult<()> {
+        self.rpc(&format!("<unlock><target><{}/></target></unlock>", target))?;
+        Ok(())
+    }
+
+    /// Merges `config`, the XML inside the `config` element, into `target`.
+    pub fn edit_config(&mut self, target: &str, config: &str) -> AppResult<()> {
+        self.rpc(&format!(
+            "<edit-config><target><{}/></target><config>{}</config></edit-config>",
+            target, config
+        ))?;
+        Ok(())
+    }
+
+    /// Commits the candidate configuration, to be rolled back after
+    /// `timeout` seconds unless confirmed by another commit.
+    pub fn commit_confirmed(&mut self, timeout: u32) -> AppResult<()> {
+        self.rpc(&format!(
+            "<commit><confirmed/><confirm-timeout>{}</confirm-timeout></commit>",
+            timeout
+        ))?;
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> AppResult<()> {
+        self.rpc("<commit/>")?;
+        Ok(())
+    }
+
+    /// Rolls back a confirmed commit that is not confirmed yet.
+    pub fn cancel_commit(&mut self) -> AppResult<()> {
+        self.rpc("<cancel-commit/>")?;
+        Ok(())
+    }
+
+    /// Resets the candidate configuration to the running one.
+    pub fn discard_changes(&mut self) -> AppResult<()> {
+        self.rpc("<discard-changes/>")?;
+        Ok(())
+    }
+
+    pub fn close(mut self) -> AppResult<()> {
+        self.rpc("<close-session/>")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        io::BufReader,
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Operations a stand-in server received, one string per rpc.
+    pub(crate) type Received = Arc<Mutex<Vec<String>>>;
+
+    /// Minimal NETCONF server announcing `capabilities`. It answers every rpc
+    /// with ok, except those containing `fail`, and records them.
+    pub(crate) fn stand_in(
+        capabilities: &'static [&'static str],
+        fail: &'static str,
+    ) -> (SocketAddr, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = Received::default();
+        let log = received.clone();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let conn = conn.unwrap();
+                let log = log.clone();
+                thread::spawn(move || {
+                    let mut writer = conn.try_clone().unwrap();
+                    let mut reader = BufReader::new(conn);
+                    let hello = capabilities
+                        .iter()
+                        .map(|capability| format!("<capability>{}</capability>", capability))
+                        .collect::<String>();
+                    let hello = format!(
+                        "<hello xmlns=\"{}\"><capabilities>{}</capabilities>\
+                         <session-id>1</session-id></hello>",
+                        BASE_NS, hello
+                    );
+                    write_message(&mut writer, false, &hello).unwrap();
+                    let client_hello = read_message(&mut reader, false).unwrap();
+                    let chunked = client_hello.contains(BASE_11) && capabilities.contains(&BASE_11);
+                    while let Ok(rpc) = read_message(&mut reader, chunked) {
+                        let operation = elements(&rpc, "rpc")[0].to_string();
+                        let reply = match !fail.is_empty() && operation.contains(fail) {
+                            true => {
+                                "<rpc-error><error-type>application</error-type>\
+                                     <error-tag>operation-failed</error-tag>\
+                                     <error-severity>error</error-severity>\
+                                     <error-message>'fail' &lt;here&gt;</error-message>\
+                                     </rpc-error>"
+                            }
+                            false => "<ok/>",
+                        };
+                        let reply = format!(
+                            "<rpc-reply xmlns=\"{}\" message-id=\"1\">{}</rpc-reply>",
+                            BASE_NS, reply
+                        );
+                        // logged before replying, so the log is complete
+                        // once the client has its answer
+                        let closing = operation.contains("close-session");
+                        log.lock().unwrap().push(operation);
+                        write_message(&mut writer, chunked, &reply).unwrap();
+                        if closing {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, received)
+    }
+
+    fn connect(addr: SocketAddr) -> Session<BufReader<TcpStream>, TcpStream> {
+        let conn = TcpStream::connect(addr).unwrap();
+        Session::new(BufReader::new(conn.try_clone().unwrap()), conn).unwrap()
+    }
+
+    #[test]
+    fn frames_messages() {
+        for &chunked in &[false, true] {
+            let mut buf = Vec::new();
+            write_message(&mut buf, chunked, "<a/>").unwrap();
+            write_message(&mut buf, chunked, "<b>]]></b>").unwrap();
+            let mut reader = &buf[..];
+            assert_eq!(read_message(&mut reader, chunked).unwrap(), "<a/>");
+            assert_eq!(read_message(&mut reader, chunked).unwrap(), "<b>]]></b>");
+        }
+        let mut reader = &b"\n#3\n<a/\n#1\n>\n##\n"[..];
+        assert_eq!(read_message(&mut reader, true).unwrap(), "<a/>");
+        assert!(read_message(&mut &b"<a/>"[..], false).is_err());
+    }
+
+    #[test]
+    fn finds_elements() {
+        let xml = "<nc:rpc-reply><nc:rpc-error><error-message xml:lang=\"en\">x</error-message>\
+                   </nc:rpc-error><ok/></nc:rpc-reply>";
+        assert_eq!(elements(xml, "error-message"), vec!["x"]);
+        assert_eq!(elements(xml, "ok"), vec![""]);
+        assert_eq!(elements(xml, "rpc-error").len(), 1);
+        assert!(elements(xml, "rpc").is_empty());
+    }
+
+    #[test]
+    fn runs_rpcs() {
+        for &capabilities in &[&[BASE_10, CANDIDATE][..], &[BASE_10, BASE_11][..]] {
+            let (addr, received) = stand_in(capabilities, "discard-changes");
+            let mut session = connect(addr);
+            assert_eq!(session.chunked, capabilities.contains(&BASE_11));
+            assert!(session.has_capability(BASE_10));
+            session.lock("candidate").unwrap();
+            session.edit_config("candidate", "<x>&amp;</x>").unwrap();
+            let e = session.discard_changes().unwrap_err();
+            assert_eq!(e.to_string(), "'fail' <here>");
+            session.close().unwrap();
+            assert_eq!(
+                *received.lock().unwrap(),
+                vec![
+                    "<lock><target><candidate/></target></lock>",
+                    "<edit-config><target><candidate/></target>\
+                     <config><x>&amp;</x></config></edit-config>",
+                    "<discard-changes/>",
+                    "<close-session/>",
+                ]
+            );
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Export policy of aut-num objects (RFC 2622, section 6.2, and RFC 4012).
+//!
+//! Only simple rules are understood:
+//!
+//! ```text
+//! export:    [protocol P1] [into P2] to <peering> [action ...;] ... announce <filter>
+//! mp-export: [afi <afi-list>] to <peering> [action ...;] ... announce <filter>
+//! ```
+//!
+//! where a peering is aimed at an AS if it names that AS or `AS-ANY`.
+
+use std::net::IpAddr;
+
+use crate::{filter::Filter, irr::parse_attributes, AppResult};
+
+/// What an AS announces to a peer in one export rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub v4: bool,
+    pub v6: bool,
+    pub filter: Filter,
+}
+
+impl Export {
+    /// Whether the rule covers the address family of `ip`.
+    pub fn covers_family(&self, ip: &IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(_) => self.v4,
+            IpAddr::V6(_) => self.v6,
+        }
+    }
+}
+
+/// The rules in aut-num `object` that export to `peer`.
+pub fn exports_to(object: &str, peer: u32) -> AppResult<Vec<Export>> {
+    let mut exports = Vec::new();
+    for (name, value) in parse_attributes(object) {
+        let multiprotocol = match name {
+            "export" => false,
+            "mp-export" => true,
+            _ => continue,
+        };
+        if let Some(export) = parse_export(&value, multiprotocol, peer)
+            .map_err(|e| format!("{}: {}: {}", name, value, e))?
+        {
+            exports.push(export);
+        }
+    }
+    Ok(exports)
+}
+
+/// Parses one rule, returning `None` if it doesn't apply to `peer`.
+fn parse_export(value: &str, multiprotocol: bool, peer: u32) -> AppResult<Option<Export>> {
+    let lower = value.to_ascii_lowercase();
+
+    // the AS expressions of each `to` peering, up to `at`, `action` or
+    // `announce`, looked for in the whole rule first so that rules for
+    // other peers are skipped whatever they contain
+    let mut aimed_at_peer = false;
+    let mut in_peering = false;
+    let tokens = lower
+        .split(|c: char| c.is_whitespace() || c == '{' || c == '}' || c == ';')
+        .filter(|token| !token.is_empty());
+    for token in tokens {
+        match token {
+            "to" => in_peering = true,
+            "at" | "action" | "announce" | "refine" | "except" => in_peering = false,
+            token if in_peering => {
+                aimed_at_peer |= token == "as-any" || token == format!("as{}", peer)
+            }
+            _ => (),
+        }
+    }
+    if !aimed_at_peer {
+        return Ok(None);
+    }
+
+    if lower.trim_start().starts_with('{')
+        || lower.contains(" refine ")
+        || lower.contains(" except ")
+    {
+        return Err("structured policies are not supported".into());
+    }
+    let announce = lower.find(" announce ").ok_or("missing announce")?;
+    let (head, filter) = (&value[..announce], value[announce + 10..].trim());
+    let words: Vec<&str> = head.split_whitespace().collect();
+
+    // `export` is IPv4 only, `mp-export` without afi covers every family
+    let (mut v4, mut v6) = (true, multiprotocol);
+    if multiprotocol && words.first().map(|w| w.eq_ignore_ascii_case("afi")) == Some(true) {
+        let afis: Vec<String> = words[1..]
+            .iter()
+            .take_while(|w| !w.eq_ignore_ascii_case("to"))
+            .flat_map(|w| w.split(','))
+            .filter(|afi| !afi.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect();
+        let any = afis.iter().any(|afi| afi.starts_with("any"));
+        v4 = any || afis.iter().any(|afi| afi.starts_with("ipv4"));
+        v6 = any || afis.iter().any(|afi| afi.starts_with("ipv6"));
+    }
+    Ok(Some(Export {
+        v4,
+        v6,
+        filter: Filter::parse(filter)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_exports_to_peer() {
+        let object = "aut-num:    AS65000\n\
+                      export:     to AS64500 action community .= { 65000:1 }; announce AS-THEM\n\
+                      export:     to AS64501 announce ANY\n\
+                      mp-export:  afi ipv6.unicast to AS64500 192.0.2.1 at 192.0.2.2\n\
+                      \x20           announce AS65000 OR {2001:db8::/32^+}\n\
+                      mp-export:  to AS-ANY announce AS65000\n\
+                      source:     RADB\n";
+        let exports = exports_to(object, 64500).unwrap();
+        assert_eq!(exports.len(), 3);
+        assert_eq!(exports[0].filter.to_string(), "AS-THEM");
+        assert_eq!((exports[0].v4, exports[0].v6), (true, false));
+        assert_eq!(
+            exports[1].filter.to_string(),
+            "AS65000 OR {2001:db8::/32^+}"
+        );
+        assert_eq!((exports[1].v4, exports[1].v6), (false, true));
+        assert_eq!((exports[2].v4, exports[2].v6), (true, true));
+        assert_eq!(exports_to(object, 64502).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn skips_unsupported_rules_for_other_peers() {
+        let object = "aut-num:    AS65000\n\
+                      export:     to AS64501 announce AS-THEM refine { to AS64501 announce ANY; }\n\
+                      export:     { to AS64502 announce ANY; }\n\
+                      export:     to AS64503\n\
+                      export:     to AS64500 announce AS-THEM\n\
+                      source:     RADB\n";
+        let exports = exports_to(object, 64500).unwrap();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].filter.to_string(), "AS-THEM");
+        for peer in [64501, 64502, 64503].iter() {
+            assert!(exports_to(object, *peer).is_err());
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Route origin validation against a local VRP export.
+//!
+//! Reads the validated ROA payloads written by rpki-client or Routinator, in
+//! their JSON or CSV formats, and classifies `(prefix, origin AS)` pairs as
+//! described in RFC 6811.
+
+use std::{fmt, fs};
+
+use serde_derive::Deserialize;
+
+use crate::{
+    aggregate::{truncate, AggPrefix},
+    irr::parse_prefix,
+    AppResult, Map, Prefix,
+};
+
+/// Validated ROA payload.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Vrp {
+    pub prefix: Prefix,
+    pub max_length: u8,
+    pub asn: u32,
+}
+
+impl Vrp {
+    /// Checks that `max_length` lies between the prefix length and the
+    /// address width.
+    pub fn new(prefix: Prefix, max_length: u8, asn: u32) -> AppResult<Self> {
+        let width = if prefix.0.is_ipv4() { 32 } else { 128 };
+        if max_length < prefix.1 || max_length > width {
+            return Err(format!(
+                "invalid max length {} for {}/{}",
+                max_length, prefix.0, prefix.1
+            )
+            .into());
+        }
+        Ok(Vrp {
+            prefix,
+            max_length,
+            asn,
+        })
+    }
+
+    /// Filter entry permitting everything this VRP authorizes.
+    pub fn to_entry(&self) -> AggPrefix {
+        let mut entry = AggPrefix::from_prefix(&self.prefix);
+        entry.max = self.max_length;
+        entry
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Validity {
+    Valid,
+    Invalid,
+    NotFound,
+}
+
+impl fmt::Display for Validity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Validity::Valid => "valid",
+            Validity::Invalid => "invalid",
+            Validity::NotFound => "not-found",
+        })
+    }
+}
+
+/// VRPs indexed by prefix and by AS number.
+#[derive(Debug, Default)]
+pub struct VrpTable {
+    vrps: Map<Prefix, Vec<Vrp>>,
+    by_asn: Map<u32, Vec<Vrp>>,
+}
+
+#[derive(Deserialize)]
+struct JsonExport {
+    roas: Vec<JsonRoa>,
+}
+
+#[derive(Deserialize)]
+struct JsonRoa {
+    asn: JsonAsn,
+    prefix: String,
+    #[serde(rename = "maxLength")]
+    max_length: u8,
+}
+
+/// Routinator writes `"AS64500"`, rpki-client writes `64500`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonAsn {
+    Num(u32),
+    Str(String),
+}
+
+fn parse_asn(input: &str) -> AppResult<u32> {
+    let digits = match input.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &input[2..],
+        _ => input,
+    };
+    Ok(digits
+        .parse()
+        .map_err(|_| format!("invalid AS number {}", input))?)
+}
+
+impl VrpTable {
+    /// Loads a JSON or CSV export, depending on whether the file starts with `{`.
+    pub fn load(file_name: &str) -> AppResult<Self> {
+        let contents = fs::read_to_string(file_name)
+            .map_err(|e| format!("failed to read {}: {}", file_name, e))?;
+        let vrps = if contents.trim_start().starts_with('{') {
+            Self::parse_json(&contents)
+        } else {
+            Self::parse_csv(&contents)
+        };
+        let vrps = vrps.map_err(|e| format!("failed to parse {}: {}", file_name, e))?;
+        Ok(vrps.into_iter().collect())
+    }
+
+    pub fn parse_json(input: &str) -> AppResult<Vec<Vrp>> {
+        let export: JsonExport = serde_json::from_str(input)?;
+        export
+            .roas
+            .into_iter()
+            .map(|roa| {
+                let asn = match roa.asn {
+                    JsonAsn::Num(asn) => asn,
+                    JsonAsn::Str(asn) => parse_asn(&asn)?,
+                };
+                Vrp::new(parse_prefix(&roa.prefix)?, roa.max_length, asn)
+            })
+            .collect()
+    }
+
+    /// Parses `ASN,IP Prefix,Max Length,...` lines; a header line is skipped.
+    pub fn parse_csv(input: &str) -> AppResult<Vec<Vrp>> {
+        input
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with("ASN"))
+            .map(|line| {
+                let mut fields = line.split(',').map(str::trim);
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(asn), Some(prefix), Some(max_length)) => Vrp::new(
+                        parse_prefix(prefix)?,
+                        max_length
+                            .parse()
+                            .map_err(|_| format!("invalid max length in {}", line))?,
+                        parse_asn(asn)?,
+                    ),
+                    _ => Err(format!("invalid VRP line {}", line).into()),
+                }
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.vrps.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vrps.is_empty()
+    }
+
+    /// VRPs whose prefix covers `prefix`, least specific first.
+    pub fn covering<'a>(&'a self, (ip, masklen): &Prefix) -> impl Iterator<Item = &'a Vrp> {
+        let ip = *ip;
+        (0..=*masklen)
+            .filter_map(move |len| self.vrps.get(&(truncate(ip, len), len)))
+            .flatten()
+    }
+
+    /// VRPs authorizing `asn` to originate routes.
+    pub fn for_asn(&self, asn: u32) -> &[Vrp] {
+        self.by_asn.get(&asn).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Route origin validation as in RFC 6811.
+    pub fn validate(&self, prefix: &Prefix, origin: u32) -> Validity {
+        let mut covered = false;
+        for vrp in self.covering(prefix) {
+            covered = true;
+            // AS0 ROAs (RFC 7607) never match
+            if vrp.asn != 0 && vrp.asn == origin && prefix.1 <= vrp.max_length {
+                return Validity::Valid;
+            }
+        }
+        if covered {
+            Validity::Invalid
+        } else {
+            Validity::NotFound
+        }
+    }
+
+    /// The routes of each AS number in `autnums` that are not invalid, and
+    /// the origins of each invalid route left out.
+    pub fn split_invalid(
+        &self,
+        autnums: &Map<u32, Vec<Prefix>>,
+    ) -> (Map<u32, Vec<Prefix>>, Map<Prefix, Vec<u32>>) {
+        let mut kept = Map::new();
+        let mut invalid: Map<Prefix, Vec<u32>> = Map::new();
+        for (num, prefixes) in autnums {
+            let mut routes = Vec::with_capacity(prefixes.len());
+            for prefix in prefixes {
+                match self.validate(prefix, *num) {
+                    Validity::Invalid => invalid.entry(*prefix).or_default().push(*num),
+                    _ => routes.push(*prefix),
+                }
+            }
+            kept.insert(*num, routes);
+        }
+        (kept, invalid)
+    }
+}
+
+impl std::iter::FromIterator<Vrp> for VrpTable {
+    fn from_iter<I: IntoIterator<Item = Vrp>>(iter: I) -> Self {
+        let mut table = VrpTable::default();
+        for vrp in iter {
+            let key = (truncate(vrp.prefix.0, vrp.prefix.1), vrp.prefix.1);
+            table.by_asn.entry(vrp.asn).or_default().push(vrp.clone());
+            table.vrps.entry(key).or_default().push(vrp);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        filter::{Filter, FilterSets},
+        format::CiscoEntryFmt,
+        rpsl::RouteSets,
+    };
+
+    fn prefix(s: &str) -> Prefix {
+        parse_prefix(s).unwrap()
+    }
+
+    #[test]
+    fn parses_exports() {
+        let routinator = r#"{"roas": [
+            {"asn": "AS64500", "prefix": "192.0.2.0/24", "maxLength": 24, "ta": "ripe"}
+        ]}"#;
+        let rpki_client = r#"{"metadata": {}, "roas": [
+            {"asn": 64500, "prefix": "192.0.2.0/24", "maxLength": 24, "ta": "ripe", "expires": 1}
+        ]}"#;
+        let csv = "ASN,IP Prefix,Max Length,Trust Anchor\nAS64500,192.0.2.0/24,24,ripe\n";
+        let expected = vec![Vrp {
+            prefix: prefix("192.0.2.0/24"),
+            max_length: 24,
+            asn: 64500,
+        }];
+        assert_eq!(VrpTable::parse_json(routinator).unwrap(), expected);
+        assert_eq!(VrpTable::parse_json(rpki_client).unwrap(), expected);
+        assert_eq!(VrpTable::parse_csv(csv).unwrap(), expected);
+
+        for line in &[
+            "AS64500,192.0.2.0/24,33",
+            "AS64500,192.0.2.0/24,23",
+            "AS64500,2001:db8::/32,129",
+        ] {
+            assert!(VrpTable::parse_csv(line).is_err(), "{}", line);
+        }
+        let too_long = r#"{"roas": [{"asn": 64500, "prefix": "192.0.2.0/24", "maxLength": 33}]}"#;
+        assert_eq!(
+            VrpTable::parse_json(too_long).unwrap_err().to_string(),
+            "invalid max length 33 for 192.0.2.0/24"
+        );
+        assert!(VrpTable::parse_csv("AS64500,2001:db8::/32,128").is_ok());
+    }
+
+    #[test]
+    fn validates_origins() {
+        let table: VrpTable = vec![
+            Vrp {
+                prefix: prefix("10.0.0.0/8"),
+                max_length: 16,
+                asn: 64500,
+            },
+            Vrp {
+                prefix: prefix("2001:db8::/32"),
+                max_length: 48,
+                asn: 0,
+            },
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            table.validate(&prefix("10.1.0.0/16"), 64500),
+            Validity::Valid
+        );
+        assert_eq!(
+            table.validate(&prefix("10.1.1.0/24"), 64500),
+            Validity::Invalid
+        );
+        assert_eq!(
+            table.validate(&prefix("10.1.0.0/16"), 64501),
+            Validity::Invalid
+        );
+        assert_eq!(
+            table.validate(&prefix("2001:db8::/48"), 0),
+            Validity::Invalid
+        );
+        assert_eq!(
+            table.validate(&prefix("192.0.2.0/24"), 64500),
+            Validity::NotFound
+        );
+    }
+
+    #[test]
+    fn leaves_out_invalid_routes() {
+        let table: VrpTable = vec![Vrp {
+            prefix: prefix("10.0.0.0/8"),
+            max_length: 16,
+            asn: 64500,
+        }]
+        .into_iter()
+        .collect();
+        let autnums: Map<u32, Vec<Prefix>> = vec![
+            (64500, vec![prefix("10.1.0.0/16"), prefix("10.1.1.0/24")]),
+            (64501, vec![prefix("10.2.0.0/16"), prefix("192.0.2.0/24")]),
+        ]
+        .into_iter()
+        .collect();
+        let (kept, invalid) = table.split_invalid(&autnums);
+        assert_eq!(kept[&64500], vec![prefix("10.1.0.0/16")]);
+        assert_eq!(kept[&64501], vec![prefix("192.0.2.0/24")]);
+        assert_eq!(invalid[&prefix("10.1.1.0/24")], vec![64500]);
+        assert_eq!(invalid[&prefix("10.2.0.0/16")], vec![64501]);
+
+        // filter expressions evaluated against the routes kept, as for
+        // route-sets, filter-sets, named filters and policies
+        let route_sets = Map::new();
+        let as_sets: Map<&str, Vec<u32>> = vec![("AS-FOO", vec![64501])].into_iter().collect();
+        let filter_sets = Map::new();
+        let resolver = FilterSets {
+            filter_sets: &filter_sets,
+            route_sets: RouteSets {
+                route_sets: &route_sets,
+                as_sets: &as_sets,
+                autnums: &kept,
+            },
+        };
+        let filter = Filter::parse("AS64500 OR AS-FOO").unwrap();
+        let entries: Vec<String> = resolver
+            .eval_filter(&filter)
+            .unwrap()
+            .iter()
+            .map(|entry| CiscoEntryFmt(entry).to_string())
+            .collect();
+        assert_eq!(entries, vec!["10.1.0.0/16", "192.0.2.0/24"]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Route-set members as described in RFC 2622, section 5.
+//!
+//! A member is an address prefix, an AS number, an as-set or another
+//! route-set, each optionally followed by a range operator (`^-`, `^+`, `^n`
+//! or `^n-m`). AS numbers and as-sets stand for the routes they originate.
+
+use std::{convert::TryFrom, fmt};
+
+use crate::{
+    aggregate::AggPrefix, filterclass::FilterClass, irr::parse_prefix, AppResult, Map, Prefix, Set,
+};
+
+/// Range operator applied to a route-set member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RangeOp {
+    /// no operator: the prefix itself
+    None,
+    /// `^-`: exclusive more specifics
+    Minus,
+    /// `^+`: inclusive more specifics
+    Plus,
+    /// `^n`: more specifics of length n
+    Exact(u8),
+    /// `^n-m`: more specifics of length n to m
+    Range(u8, u8),
+}
+
+impl RangeOp {
+    /// Parses the operator without its leading `^`.
+    pub(crate) fn parse(input: &str) -> AppResult<Self> {
+        let num = |s: &str| {
+            s.parse::<u8>()
+                .map_err(|_| format!("invalid range operator ^{}", input))
+        };
+        Ok(match input {
+            "-" => RangeOp::Minus,
+            "+" => RangeOp::Plus,
+            _ => match input.find('-') {
+                Some(pos) => RangeOp::Range(num(&input[..pos])?, num(&input[pos + 1..])?),
+                None => RangeOp::Exact(num(input)?),
+            },
+        })
+    }
+
+    /// Applies the operator to every prefix permitted by `entry`.
+    ///
+    /// The result is `None` if no prefix is left, e.g. `^16` on a /24.
+    pub fn apply(self, entry: &AggPrefix) -> Option<AggPrefix> {
+        let family_len = if entry.prefix.is_ipv4() { 32 } else { 128 };
+        let (min, max) = match self {
+            RangeOp::None => (entry.min, entry.max),
+            RangeOp::Minus => (entry.min + 1, family_len),
+            RangeOp::Plus => (entry.min, family_len),
+            RangeOp::Exact(n) => (n.max(entry.min), n),
+            RangeOp::Range(n, m) => (n.max(entry.min), m),
+        };
+        if min <= max && max <= family_len {
+            Some(AggPrefix {
+                min,
+                max,
+                ..entry.clone()
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for RangeOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeOp::None => Ok(()),
+            RangeOp::Minus => f.write_str("^-"),
+            RangeOp::Plus => f.write_str("^+"),
+            RangeOp::Exact(n) => write!(f, "^{}", n),
+            RangeOp::Range(n, m) => write!(f, "^{}-{}", n, m),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RouteSetMember {
+    Prefix(Prefix, RangeOp),
+    AutNum(u32, RangeOp),
+    AsSet(String, RangeOp),
+    RouteSet(String, RangeOp),
+}
+
+impl RouteSetMember {
+    /// Parses a member such as `192.0.2.0/24^+`, `AS64500`, `AS-FOO^24` or `RS-BAR`.
+    pub fn parse(input: &str) -> AppResult<Self> {
+        let (base, op) = match input.find('^') {
+            Some(pos) => (&input[..pos], RangeOp::parse(&input[pos + 1..])?),
+            None => (input, RangeOp::None),
+        };
+        if base.contains('/') {
+            return Ok(RouteSetMember::Prefix(parse_prefix(base)?, op));
+        }
+        match FilterClass::try_from(base) {
+            Ok(FilterClass::AutNum(num)) => Ok(RouteSetMember::AutNum(num, op)),
+            Ok(FilterClass::AsSet(name)) => Ok(RouteSetMember::AsSet(name.to_string(), op)),
+            Ok(FilterClass::RouteSet(name)) => Ok(RouteSetMember::RouteSet(name.to_string(), op)),
+            Ok(FilterClass::FilterSet(_)) | Ok(FilterClass::Policy(..)) | Err(_) => {
+                Err(format!("invalid route-set member {}", input).into())
+            }
+        }
+    }
+}
+
+impl fmt::Display for RouteSetMember {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteSetMember::Prefix((ip, len), op) => write!(f, "{}/{}{}", ip, len, op),
+            RouteSetMember::AutNum(num, op) => write!(f, "AS{}{}", num, op),
+            RouteSetMember::AsSet(name, op) | RouteSetMember::RouteSet(name, op) => {
+                write!(f, "{}{}", name, op)
+            }
+        }
+    }
+}
+
+/// Fetches `roots` and every route-set nested in them.
+///
+/// `query` resolves a batch of route-sets; nested sets the server didn't
+/// expand itself are queried in further rounds. Names are kept as written.
+pub fn fetch_route_sets<F>(
+    roots: &Set<&str>,
+    mut query: F,
+) -> AppResult<Map<String, Vec<RouteSetMember>>>
+where
+    F: for<'q> FnMut(&Set<&'q str>) -> AppResult<Map<&'q str, Vec<RouteSetMember>>>,
+{
+    let mut route_sets: Map<String, Vec<RouteSetMember>> = Map::new();
+    let mut seen: Set<String> = roots.iter().map(|s| s.to_ascii_uppercase()).collect();
+    let mut frontier: Vec<String> = roots.iter().map(|s| s.to_string()).collect();
+    while !frontier.is_empty() {
+        let batch: Set<&str> = frontier.iter().map(String::as_str).collect();
+        let mut next = Vec::new();
+        for (set, members) in query(&batch)? {
+            for member in members.iter() {
+                if let RouteSetMember::RouteSet(name, _) = member {
+                    if seen.insert(name.to_ascii_uppercase()) {
+                        next.push(name.clone());
+                    }
+                }
+            }
+            route_sets.insert(set.to_string(), members);
+        }
+        frontier = next;
+    }
+    Ok(route_sets)
+}
+
+/// The data a route-set is flattened against.
+#[derive(Clone, Copy)]
+pub struct RouteSets<'a> {
+    /// members of each route-set, as returned by [`fetch_route_sets`]
+    pub route_sets: &'a Map<String, Vec<RouteSetMember>>,
+    /// AS numbers of each as-set mentioned in a route-set
+    pub as_sets: &'a Map<&'a str, Vec<u32>>,
+    /// prefixes originated by each AS number mentioned, directly or via an as-set
+    pub autnums: &'a Map<u32, Vec<Prefix>>,
+}
+
+impl RouteSets<'_> {
+    /// AS numbers and as-sets mentioned in any of the route-sets.
+    pub fn references(route_sets: &Map<String, Vec<RouteSetMember>>) -> (Set<u32>, Set<&str>) {
+        let mut autnums = Set::new();
+        let mut as_sets = Set::new();
+        for member in route_sets.values().flatten() {
+            match member {
+                RouteSetMember::AutNum(num, _) => {
+                    autnums.insert(*num);
+                }
+                RouteSetMember::AsSet(name, _) => {
+                    as_sets.insert(name.as_str());
+                }
+                _ => (),
+            }
+        }
+        (autnums, as_sets)
+    }
+
+    /// Filter entries permitted by route-set `name`, each with the chain of
+    /// route-sets and the member it comes from.
+    pub fn flatten(&self, name: &str) -> Vec<(Vec<String>, AggPrefix)> {
+        self.flatten_inner(name, &mut Set::new())
+    }
+
+    /// Filter entries permitted by a single member, e.g. of a filter expression.
+    pub fn member_entries(&self, member: &RouteSetMember) -> Vec<(Vec<String>, AggPrefix)> {
+        self.member_inner(member, &mut Set::new())
+    }
+
+    fn flatten_inner(&self, name: &str, seen: &mut Set<String>) -> Vec<(Vec<String>, AggPrefix)> {
+        let mut ret = Vec::new();
+        // a route-set nested in itself adds nothing; `seen` holds the sets
+        // currently being flattened
+        if !seen.insert(name.to_ascii_uppercase()) {
+            return ret;
+        }
+        let members = self
+            .route_sets
+            .iter()
+            .find(|(set, _)| set.eq_ignore_ascii_case(name))
+            .map(|(_, members)| members.as_slice())
+            .unwrap_or(&[]);
+        for member in members {
+            for (mut path, entry) in self.member_inner(member, seen) {
+                path.insert(0, name.to_string());
+                ret.push((path, entry));
+            }
+        }
+        seen.remove(&name.to_ascii_uppercase());
+        ret
+    }
+
+    fn member_inner(
+        &self,
+        member: &RouteSetMember,
+        seen: &mut Set<String>,
+    ) -> Vec<(Vec<String>, AggPrefix)> {
+        let prefixes = |num: &u32| -> Vec<(Vec<String>, AggPrefix)> {
+            self.autnums
+                .get(num)
+                .into_iter()
+                .flatten()
+                .map(|prefix| (vec![], AggPrefix::from_prefix(prefix)))
+                .collect()
+        };
+        let (entries, op) = match member {
+            RouteSetMember::Prefix(prefix, op) => {
+                (vec![(vec![], AggPrefix::from_prefix(prefix))], op)
+            }
+            RouteSetMember::AutNum(num, op) => (prefixes(num), op),
+            RouteSetMember::AsSet(set, op) => (
+                self.as_sets
+                    .get(set.as_str())
+                    .into_iter()
+                    .flatten()
+                    .flat_map(prefixes)
+                    .collect(),
+                op,
+            ),
+            RouteSetMember::RouteSet(set, op) => {
+                let mut entries = self.flatten_inner(set, seen);
+                // the member names the nested set already
+                for (path, _) in entries.iter_mut() {
+                    path.remove(0);
+                }
+                (entries, op)
+            }
+        };
+        entries
+            .into_iter()
+            .filter_map(|(mut path, entry)| {
+                path.insert(0, member.to_string());
+                Some((path, op.apply(&entry)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(s: &str) -> AggPrefix {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn applies_range_operators() {
+        let prefix = AggPrefix::from_prefix(&parse_prefix("192.0.2.0/24").unwrap());
+        let apply = |op| RangeOp::parse(op).unwrap().apply(&prefix);
+        assert_eq!(apply("-"), Some(entry("192.0.2.0/24 ge 25 le 32")));
+        assert_eq!(apply("+"), Some(entry("192.0.2.0/24 le 32")));
+        assert_eq!(apply("26"), Some(entry("192.0.2.0/24 ge 26 le 26")));
+        assert_eq!(apply("25-28"), Some(entry("192.0.2.0/24 ge 25 le 28")));
+        assert_eq!(apply("16"), None);
+        // an operator on a range applies to each prefix in it
+        let range = entry("10.0.0.0/8 ge 24 le 32");
+        assert_eq!(
+            RangeOp::Minus.apply(&range),
+            Some(entry("10.0.0.0/8 ge 25 le 32"))
+        );
+        assert_eq!(RangeOp::Exact(16).apply(&range), None);
+    }
+
+    #[test]
+    fn flattens_route_sets() {
+        let members = |s: &str| -> Vec<RouteSetMember> {
+            s.split_whitespace()
+                .map(|m| RouteSetMember::parse(m).unwrap())
+                .collect()
+        };
+        let db: Map<&str, Vec<RouteSetMember>> = vec![
+            (
+                "RS-FOO",
+                members("192.0.2.0/24^+ AS64500 AS-BAR^24 rs-bar^-"),
+            ),
+            ("RS-BAR", members("198.51.100.0/24 RS-FOO")),
+        ]
+        .into_iter()
+        .collect();
+        let roots: Set<&str> = ["RS-FOO"].iter().copied().collect();
+        let route_sets = fetch_route_sets(&roots, |sets| {
+            Ok(sets
+                .iter()
+                .map(|set| (*set, db[set.to_ascii_uppercase().as_str()].clone()))
+                .collect())
+        })
+        .unwrap();
+        assert_eq!(route_sets.len(), 2);
+        let (autnums, as_sets) = RouteSets::references(&route_sets);
+        assert_eq!(autnums.into_iter().collect::<Vec<_>>(), vec![64500]);
+        assert_eq!(as_sets.into_iter().collect::<Vec<_>>(), vec!["AS-BAR"]);
+
+        let as_set_members: Map<&str, Vec<u32>> =
+            vec![("AS-BAR", vec![64501])].into_iter().collect();
+        let autnum_prefixes: Map<u32, Vec<Prefix>> = vec![
+            (64500, vec![parse_prefix("203.0.113.0/24").unwrap()]),
+            (64501, vec![parse_prefix("10.0.0.0/16").unwrap()]),
+        ]
+        .into_iter()
+        .collect();
+        let resolver = RouteSets {
+            route_sets: &route_sets,
+            as_sets: &as_set_members,
+            autnums: &autnum_prefixes,
+        };
+        let mut flattened = resolver.flatten("RS-FOO");
+        flattened.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+        let expected = vec![
+            (vec!["RS-FOO", "AS-BAR^24"], "10.0.0.0/16 ge 24 le 24"),
+            (vec!["RS-FOO", "192.0.2.0/24^+"], "192.0.2.0/24 le 32"),
+            (
+                vec!["RS-FOO", "rs-bar^-", "198.51.100.0/24"],
+                "198.51.100.0/24 ge 25 le 32",
+            ),
+            (vec!["RS-FOO", "AS64500"], "203.0.113.0/24"),
+        ];
+        let expected: Vec<(Vec<String>, AggPrefix)> = expected
+            .into_iter()
+            .map(|(path, e)| (path.into_iter().map(String::from).collect(), entry(e)))
+            .collect();
+        assert_eq!(flattened, expected);
+    }
+}
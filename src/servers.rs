@@ -0,0 +1,405 @@
+//! Several IRR servers used as one: each a fallback for the previous one, or
+//! all queried with their answers merged.
+
+use std::{hash::Hash, net::SocketAddr, str::FromStr};
+
+use crate::{irr::IrrPool, rpsl::RouteSetMember, AppResult, Map, Prefix, RouteObject, Set};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// ask one server, moving on to the next on connection or protocol errors
+    Failover,
+    /// ask every server reachable at first and take the union of their
+    /// answers, failing if one of them stops answering
+    Merge,
+}
+
+impl FromStr for Strategy {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "failover" => Ok(Strategy::Failover),
+            "merge" => Ok(Strategy::Merge),
+            _ => Err(format!("unknown server strategy {}", s).into()),
+        }
+    }
+}
+
+/// Servers holding the same sources.
+struct Group {
+    /// server names, with a pool while connected
+    servers: Vec<(String, Option<IrrPool>)>,
+    sources: String,
+    /// the server failover queries go to first
+    current: usize,
+    /// the servers merged queries go to, those reachable when opened
+    merged: Vec<usize>,
+}
+
+/// IRR servers queried according to a [`Strategy`].
+///
+/// Sources can be routed to servers of their own, e.g. RIPE to
+/// whois.ripe.net: each query then goes to every group of servers, and the
+/// answers are merged.
+pub struct IrrServers {
+    groups: Vec<Group>,
+    connections: usize,
+    strategy: Strategy,
+    answers: Vec<(String, Vec<String>)>,
+    failures: Vec<(String, String)>,
+}
+
+impl IrrServers {
+    /// Connects to the first reachable server, or with `Strategy::Merge` to
+    /// every reachable one.
+    pub fn open<S: AsRef<str>>(
+        servers: &[S],
+        sources: &str,
+        connections: usize,
+        strategy: Strategy,
+    ) -> AppResult<Self> {
+        IrrServers::open_routed(&[(servers, sources)], connections, strategy)
+    }
+
+    /// Like [`IrrServers::open`], with each list of comma-separated sources
+    /// queried at its own servers.
+    pub fn open_routed<S: AsRef<str>, T: AsRef<str>>(
+        routes: &[(&[S], T)],
+        connections: usize,
+        strategy: Strategy,
+    ) -> AppResult<Self> {
+        let groups = routes
+            .iter()
+            .map(|(servers, sources)| Group {
+                servers: servers
+                    .iter()
+                    .map(|server| (server.as_ref().to_string(), None))
+                    .collect(),
+                sources: sources.as_ref().to_string(),
+                current: 0,
+                merged: Vec::new(),
+            })
+            .collect();
+        let mut ret = IrrServers {
+            groups,
+            connections,
+            strategy,
+            answers: Vec::new(),
+            failures: Vec::new(),
+        };
+        for g in 0..ret.groups.len() {
+            for i in 0..ret.groups[g].servers.len() {
+                if ret.connect(g, i).is_ok() && strategy == Strategy::Failover {
+                    ret.groups[g].current = i;
+                    break;
+                }
+            }
+            let group = &mut ret.groups[g];
+            group.merged = (0..group.servers.len())
+                .filter(|i| group.servers[*i].1.is_some())
+                .collect();
+            if group.merged.is_empty() {
+                let failures: Vec<String> = ret
+                    .failures
+                    .iter()
+                    .map(|(server, e)| format!("{}: {}", server, e))
+                    .collect();
+                return Err(format!(
+                    "no IRR server reachable for {} ({})",
+                    group.sources,
+                    failures.join("; ")
+                )
+                .into());
+            }
+        }
+        Ok(ret)
+    }
+
+    fn connect(&mut self, g: usize, i: usize) -> AppResult<&mut IrrPool> {
+        let group = &mut self.groups[g];
+        let (server, pool) = &mut group.servers[i];
+        if pool.is_none() {
+            match IrrPool::open(server.as_str(), &group.sources, self.connections) {
+                Ok(opened) => *pool = Some(opened),
+                Err(e) => {
+                    self.failures.push((server.clone(), e.to_string()));
+                    return Err(e);
+                }
+            }
+        }
+        Ok(pool.as_mut().expect("BUG: not connected"))
+    }
+
+    /// Connected servers, their addresses and the sources queried there.
+    pub fn peers(&self) -> Vec<(&str, SocketAddr, &str)> {
+        self.groups
+            .iter()
+            .flat_map(|group| {
+                group.servers.iter().filter_map(move |(server, pool)| {
+                    let addr = pool.as_ref()?.peer_addr().ok()?;
+                    Some((server.as_str(), addr, group.sources.as_str()))
+                })
+            })
+            .collect()
+    }
+
+    /// Connections per server.
+    pub fn size(&self) -> usize {
+        self.connections.max(1)
+    }
+
+    /// Each query so far, with the servers that answered it (and their
+    /// sources, if routed).
+    pub fn answers(&self) -> &[(String, Vec<String>)] {
+        &self.answers
+    }
+
+    /// Each server that failed so far, with the error.
+    pub fn failures(&self) -> &[(String, String)] {
+        &self.failures
+    }
+
+    /// Runs `resolve` on the servers the strategy picks in every group,
+    /// recording it as `count` times `what` in the answers.
+    fn query<T, F, M>(&mut self, count: usize, what: &str, mut resolve: F, merge: M) -> AppResult<T>
+    where
+        F: FnMut(&mut IrrPool) -> AppResult<T>,
+        M: Fn(&mut T, T),
+    {
+        let mut ret: Option<T> = None;
+        let mut answered = Vec::new();
+        let routed = self.groups.len() > 1;
+        for g in 0..self.groups.len() {
+            let servers = self.groups[g].servers.len();
+            let current = self.groups[g].current;
+            let order: Vec<usize> = match self.strategy {
+                Strategy::Failover => (0..servers).map(|i| (current + i) % servers).collect(),
+                Strategy::Merge => self.groups[g].merged.clone(),
+            };
+            let mut group_answered = false;
+            let mut last_error = None;
+            for i in order {
+                let result = self.connect(g, i).and_then(&mut resolve);
+                let group = &mut self.groups[g];
+                let (server, pool) = &mut group.servers[i];
+                match result {
+                    Ok(answer) => {
+                        answered.push(if routed {
+                            format!("{} ({})", server, group.sources)
+                        } else {
+                            server.clone()
+                        });
+                        group_answered = true;
+                        match ret.as_mut() {
+                            Some(ret) => merge(ret, answer),
+                            None => ret = Some(answer),
+                        }
+                        if self.strategy == Strategy::Failover {
+                            group.current = i;
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        // a failed connection is dropped and reopened when needed
+                        if pool.take().is_some() {
+                            self.failures.push((server.clone(), e.to_string()));
+                        }
+                        // a union without one of the servers is incomplete
+                        if self.strategy == Strategy::Merge {
+                            return Err(format!("{}: {}", server, e).into());
+                        }
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if !group_answered {
+                return Err(last_error.unwrap_or_else(|| "no IRR servers".into()));
+            }
+        }
+        if count > 0 {
+            self.answers.push((format!("{} {}", count, what), answered));
+        }
+        ret.ok_or_else(|| "no IRR servers".into())
+    }
+
+    pub fn resolve_as_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<u32>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_as_sets(sets);
+        self.query(sets.len(), "as-sets", resolve, merge_lists)
+    }
+
+    pub fn resolve_set_members<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<String>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_set_members(sets);
+        self.query(sets.len(), "as-set members", resolve, merge_lists)
+    }
+
+    pub fn resolve_route_objects(
+        &mut self,
+        prefixes: &Set<Prefix>,
+    ) -> AppResult<Map<Prefix, Vec<RouteObject>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_route_objects(prefixes);
+        self.query(prefixes.len(), "route objects", resolve, merge_lists)
+    }
+
+    pub fn resolve_route_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, Vec<RouteSetMember>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_route_sets(sets);
+        self.query(sets.len(), "route-sets", resolve, merge_lists)
+    }
+
+    pub fn resolve_objects<'a>(
+        &mut self,
+        class: &str,
+        keys: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        let what = format!("{} objects", class);
+        let resolve = |pool: &mut IrrPool| pool.resolve_objects(class, keys);
+        self.query(keys.len(), &what, resolve, merge_first)
+    }
+
+    pub fn resolve_filter_sets<'a>(
+        &mut self,
+        sets: &Set<&'a str>,
+    ) -> AppResult<Map<&'a str, String>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_filter_sets(sets);
+        self.query(sets.len(), "filter-sets", resolve, merge_first)
+    }
+
+    pub fn resolve_autnums(&mut self, autnums: &Set<u32>) -> AppResult<Map<u32, Vec<Prefix>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_autnums(autnums);
+        self.query(autnums.len(), "AS numbers' routes", resolve, merge_lists)
+    }
+
+    pub fn resolve_autnum_routes(
+        &mut self,
+        autnums: &Set<u32>,
+    ) -> AppResult<Map<u32, Vec<RouteObject>>> {
+        let resolve = |pool: &mut IrrPool| pool.resolve_autnum_routes(autnums);
+        self.query(
+            autnums.len(),
+            "AS numbers' route objects",
+            resolve,
+            merge_lists,
+        )
+    }
+}
+
+/// Adds the values of `from` missing in `into`.
+fn merge_lists<K: Eq + Hash, V: Clone + Eq + Hash>(
+    into: &mut Map<K, Vec<V>>,
+    from: Map<K, Vec<V>>,
+) {
+    for (key, values) in from {
+        let list = into.entry(key).or_default();
+        let mut seen: Set<V> = list.iter().cloned().collect();
+        list.extend(
+            values
+                .into_iter()
+                .filter(|value| seen.insert(value.clone())),
+        );
+    }
+}
+
+/// Adds the objects of `from` missing in `into`; the first answer wins.
+fn merge_first<K: Eq + Hash, V>(into: &mut Map<K, V>, from: Map<K, V>) {
+    for (key, value) in from {
+        into.entry(key).or_insert(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irr::tests::mock_server;
+    use std::net::TcpListener;
+
+    const RADB: &[(&str, &str)] = &[("!iAS-FOO,1", "AS3333"), ("!gas3333", "193.0.0.0/21")];
+    const NTT: &[(&str, &str)] = &[
+        ("!iAS-FOO,1", "AS3333 AS2914"),
+        ("!gas3333", "193.0.0.0/21 193.0.10.0/23"),
+    ];
+
+    fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn fails_over_and_merges() {
+        let sets: Set<&str> = ["AS-FOO"].iter().copied().collect();
+        let autnums: Set<u32> = [3333].iter().copied().collect();
+        let (down, radb, ntt) = (
+            closed_port(),
+            mock_server(RADB).to_string(),
+            mock_server(NTT).to_string(),
+        );
+
+        let servers = [down.clone(), radb.clone(), ntt.clone()];
+        let mut failover = IrrServers::open(&servers, "RADB", 1, Strategy::Failover).unwrap();
+        assert_eq!(
+            failover.resolve_as_sets(&sets).unwrap()["AS-FOO"],
+            vec![3333]
+        );
+        assert_eq!(failover.failures().len(), 1);
+        assert_eq!(failover.failures()[0].0, down);
+        assert_eq!(
+            failover.answers()[0],
+            ("1 as-sets".to_string(), vec![radb.clone()])
+        );
+
+        let mut merge = IrrServers::open(&servers, "RADB", 1, Strategy::Merge).unwrap();
+        assert_eq!(merge.peers().len(), 2);
+        assert_eq!(
+            merge.resolve_as_sets(&sets).unwrap()["AS-FOO"],
+            vec![3333, 2914]
+        );
+        assert_eq!(merge.resolve_autnums(&autnums).unwrap()[&3333].len(), 2);
+        assert_eq!(merge.answers()[1].1, vec![radb.clone(), ntt.clone()]);
+
+        assert!(
+            IrrServers::open(std::slice::from_ref(&down), "RADB", 1, Strategy::Failover).is_err()
+        );
+
+        // a server that accepts connections but never answers a query
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broken = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for conn in listener.incoming() {
+                drop(conn);
+            }
+        });
+        let servers = [radb.clone(), broken.clone()];
+        let mut merge = IrrServers::open(&servers, "RADB", 1, Strategy::Merge).unwrap();
+        let e = merge.resolve_as_sets(&sets).unwrap_err();
+        assert!(e.to_string().starts_with(&broken));
+        let mut failover = IrrServers::open(&servers, "RADB", 1, Strategy::Failover).unwrap();
+        assert!(failover.resolve_as_sets(&sets).is_ok());
+
+        // RIPE at its own server, the rest at the default one
+        let (ripe, default) = ([ntt.clone()], [down, radb.clone()]);
+        let routes: [(&[String], &str); 2] = [(&default, "RADB"), (&ripe, "RIPE")];
+        let mut routed = IrrServers::open_routed(&routes, 1, Strategy::Failover).unwrap();
+        let peers: Vec<(&str, &str)> = routed
+            .peers()
+            .into_iter()
+            .map(|(server, _, sources)| (server, sources))
+            .collect();
+        assert_eq!(peers, vec![(radb.as_str(), "RADB"), (ntt.as_str(), "RIPE")]);
+        assert_eq!(
+            routed.resolve_as_sets(&sets).unwrap()["AS-FOO"],
+            vec![3333, 2914]
+        );
+        assert_eq!(
+            routed.answers()[0].1,
+            vec![format!("{} (RADB)", radb), format!("{} (RIPE)", ntt)]
+        );
+    }
+}